@@ -0,0 +1,373 @@
+//! Ground station
+//!
+//! Talks to a node over a serial line: pairs with it, asks for its public key, sends commands and
+//! prints what comes back, one JSON line per message. The messages, the crypto, the framing and the
+//! key exchange are the firmware's own modules built for the host, so the two ends cannot drift
+//! apart.
+//!
+//! ```text
+//! ground-station [--baud <rate>] [--key <file>] [--capture <file>] <device | --pty> <command> [args..]
+//! ground-station [--key <file>] replay <capture> [rx | tx]
+//! ```
+//!
+//! `--pty` opens a pseudo terminal instead of a device and prints the path of its other end, for a
+//! simulated node to attach to. `--capture` records the link to a file, `replay` feeds a capture
+//! back through the node's receive path, see `capture`.
+
+mod pair;
+mod station;
+#[cfg(test)]
+mod tests;
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use capture::Direction;
+use firmware::{capture, coms_manager, config, crypt, eeprom, envelope, filter, fragment};
+use firmware::{key_store, link, messages, schema, session, temperature, wear, wire};
+use messages::{Command, Data, Message, Status};
+use station::Station;
+use temperature::{Calibration, CalibrationPoint};
+
+/// The pure modules of the firmware, everything that does not touch the board. Their tests build
+/// with this crate, the firmware image never has a test harness.
+#[path = "../../src"]
+#[allow(dead_code)]
+mod firmware {
+    pub mod capture;
+    pub mod config;
+    pub mod crypt;
+    pub mod envelope;
+    pub mod event_log;
+    pub mod filter;
+    pub mod fragment;
+    pub mod key_store;
+    pub mod link;
+    pub mod messages;
+    pub mod schema;
+    pub mod session;
+    pub mod wear;
+    pub mod wire;
+
+    pub mod coms_manager {
+        mod receiver;
+
+        pub use receiver::*;
+    }
+
+    #[cfg(test)]
+    pub mod mux {
+        mod driver;
+        pub mod mock;
+        mod tests;
+
+        pub use driver::*;
+    }
+
+    pub mod eeprom {
+        pub mod block;
+        #[cfg(test)]
+        pub mod sim;
+        mod storage;
+
+        pub use storage::*;
+    }
+
+    pub mod temperature {
+        mod calibration;
+
+        pub use calibration::*;
+    }
+}
+
+const USAGE: &str = "\
+usage: ground-station [--baud <rate>] [--key <file>] [--capture <file>] <device | --pty> <command> [args..]
+       ground-station [--key <file>] replay <capture> [rx | tx]
+
+commands:
+  pair                  run the key exchange and save the session key to the key file
+  public-key            ask for the node's RSA public key
+  command <name>        send a command and print its response, one of:
+                          delete-aes-key, wipe-all-keys, report-wear, report-cache-stats,
+                          get-config, get-calibration, read-temperature, self-test,
+                          set-calibration <low measured> <low actual> <high measured> <high actual>
+                          in °C, `measured` as `read-temperature` reports it
+  telemetry             print every message from the node until interrupted
+  log                   dump the node's event log
+  replay <capture>      print what the node made of the bytes the station sent, or with `rx`, what
+                        the station received, decoded the way the node decodes
+
+--baud defaults to the firmware's default, --key to `session.key`. --capture records everything
+that crosses the line to a file.";
+
+/// How long to wait for the node to answer.
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+struct Args {
+    /// `None` for a pseudo terminal.
+    device: Option<String>,
+    baud: u32,
+    key: PathBuf,
+    capture: Option<PathBuf>,
+    command: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut device = None;
+    let mut pty = false;
+    let mut baud = config::Config::default().baud_rate;
+    let mut key = PathBuf::from("session.key");
+    let mut capture = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baud" => baud = args.next().ok_or("--baud needs a rate")?.parse()?,
+            "--key" => key = args.next().ok_or("--key needs a file")?.into(),
+            "--capture" => capture = Some(args.next().ok_or("--capture needs a file")?.into()),
+            "--pty" => pty = true,
+            "-h" | "--help" => return Err("".into()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg).into()),
+            // a replay needs no device.
+            _ if device.is_none() && !pty && arg != "replay" => device = Some(arg),
+            _ => {
+                let command = std::iter::once(arg).chain(args).collect();
+                return Ok(Args {
+                    device,
+                    baud,
+                    key,
+                    capture,
+                    command,
+                });
+            }
+        }
+    }
+    Err("no command given".into())
+}
+
+fn run(args: Args) -> Result<()> {
+    let command: Vec<&str> = args.command.iter().map(String::as_str).collect();
+    if let ["replay", ref rest @ ..] = command[..] {
+        let direction = match rest.get(1..).unwrap_or_default() {
+            [] | ["tx"] => Direction::Tx,
+            ["rx"] => Direction::Rx,
+            _ => {
+                return Err(format!("unknown command `{}`\n\n{}", command.join(" "), USAGE).into())
+            }
+        };
+        let path = rest.first().ok_or("replay needs a capture")?;
+        return replay(path, direction, load_key(&args.key)?);
+    }
+
+    // the other end of a pseudo terminal is kept open, the simulated node may not be there yet.
+    let (port, _other_end) = open(args.device.as_deref(), args.baud)?;
+    let key = load_key(&args.key)?;
+    let mut station = Station::new(port, key);
+    if let Some(path) = &args.capture {
+        station.capture_to(fs::File::create(path)?);
+    }
+
+    match command[..] {
+        ["pair"] => {
+            let key = pair::pair(&mut station)?;
+            fs::write(&args.key, hex(&key))?;
+            println!("paired, session key saved to {}", args.key.display());
+        }
+        ["public-key"] => {
+            station.send(&Data::Status(Status::UnkownPublicKey))?;
+            let msg = wait(&mut station, |data| matches!(data, Data::RSAPublicKey(_)))?;
+            print(&msg)?;
+        }
+        ["command", ref command @ ..] => {
+            let command = parse_command(command)?;
+            let id = station.send(&Data::Command(command))?;
+            let msg = wait(&mut station, |data| answers(data, id))?;
+            print(&msg)?;
+        }
+        ["telemetry"] => loop {
+            if let Some(msg) = station.receive(Instant::now() + TIMEOUT)? {
+                print(&msg)?;
+            }
+        },
+        ["log"] => {
+            let id = station.send(&Data::Command(Command::DumpLog))?;
+            // the entries come first, each in a message of its own, then the response.
+            loop {
+                let msg = wait(&mut station, |data| {
+                    matches!(data, Data::Log(_)) || answers(data, id)
+                })?;
+                print(&msg)?;
+                if !matches!(msg.data, Data::Log(_)) {
+                    break;
+                }
+            }
+        }
+        _ => return Err(format!("unknown command `{}`\n\n{}", command.join(" "), USAGE).into()),
+    }
+    Ok(())
+}
+
+/// Print what comes out of replaying `direction` of the capture at `path`, then say whether it
+/// matches what was recorded.
+fn replay(path: &str, direction: Direction, key: Option<[u8; 8]>) -> Result<()> {
+    let text = fs::read_to_string(path)?;
+    let mut printed = Ok(());
+    capture::replay::replay(text.lines(), direction, key.as_ref(), |_, msg| {
+        if printed.is_ok() {
+            printed = print(&msg);
+        }
+    })
+    .map_err(|failure| format!("{:?}", failure))?;
+    printed?;
+    match capture::replay::verify(&text, direction, key.as_ref()) {
+        Ok(_) => eprintln!("the replay matches the capture"),
+        Err(failure) => eprintln!("the replay differs from the capture: {:?}", failure),
+    }
+    Ok(())
+}
+
+type Port = Box<dyn serialport::SerialPort>;
+
+/// The device, or a new pseudo terminal and its other end.
+fn open(device: Option<&str>, baud: u32) -> Result<(Port, Option<Port>)> {
+    match device {
+        Some(device) => {
+            let port = serialport::new(device, baud)
+                .timeout(Duration::from_millis(20))
+                .open()?;
+            Ok((port, None))
+        }
+        None => pty(),
+    }
+}
+
+#[cfg(unix)]
+fn pty() -> Result<(Port, Option<Port>)> {
+    use serialport::SerialPort;
+
+    let (mut ours, theirs) = serialport::TTYPort::pair()?;
+    ours.set_timeout(Duration::from_millis(20))?;
+    let name = theirs.name().ok_or("the pseudo terminal has no name")?;
+    eprintln!("waiting on {}", name);
+    Ok((Box::new(ours), Some(Box::new(theirs))))
+}
+
+#[cfg(not(unix))]
+fn pty() -> Result<(Port, Option<Port>)> {
+    Err("pseudo terminals need a unix host".into())
+}
+
+/// The session key from the last `pair`, if there was one.
+fn load_key(path: &PathBuf) -> Result<Option<[u8; 8]>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let text = text.trim();
+    let mut key = [0; 8];
+    if text.len() != 2 * key.len() {
+        return Err(format!("{} does not hold a session key", path.display()).into());
+    }
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)?;
+    }
+    Ok(Some(key))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        write!(text, "{:02x}", byte).unwrap();
+        text
+    })
+}
+
+fn parse_command(args: &[&str]) -> Result<Command> {
+    let command = match args {
+        ["delete-aes-key"] => Command::DeleteAESKey,
+        ["wipe-all-keys"] => Command::WipeAllKeys,
+        ["report-wear"] => Command::ReportWear,
+        ["report-cache-stats"] => Command::ReportCacheStats,
+        ["get-config"] => Command::GetConfig,
+        ["get-calibration"] => Command::GetCalibration,
+        ["read-temperature"] => Command::ReadTemperature,
+        ["self-test"] => Command::SelfTest,
+        ["set-calibration", low_measured, low_actual, high_measured, high_actual] => {
+            Command::SetCalibration(Calibration {
+                low: CalibrationPoint {
+                    measured: hundredths(low_measured)?,
+                    actual: hundredths(low_actual)?,
+                },
+                high: CalibrationPoint {
+                    measured: hundredths(high_measured)?,
+                    actual: hundredths(high_actual)?,
+                },
+            })
+        }
+        _ => return Err(format!("unknown command `{}`", args.join(" ")).into()),
+    };
+    Ok(command)
+}
+
+/// A temperature in °C as the hundredths of a degree a `CalibrationPoint` holds.
+fn hundredths(text: &str) -> Result<i16> {
+    let hundredths = (text.parse::<f32>()? * 100.0).round();
+    if !(i16::MIN as f32..=i16::MAX as f32).contains(&hundredths) {
+        return Err(format!("`{text}` is not a temperature between -327.68 and 327.67 °C").into());
+    }
+    Ok(hundredths as i16)
+}
+
+/// Whether `data` answers the command that went out under `id`. Nodes from before
+/// `Data::Response` answer with the bare value, only their errors say what they answer.
+fn answers(data: &Data, id: u8) -> bool {
+    match data {
+        Data::Response(response) => response.request_id == id,
+        Data::Status(Status::Error(report)) => report.message_id == id,
+        Data::Status(Status::KeysWiped(_) | Status::LogDumped(_) | Status::SensorFailed) => true,
+        Data::Wear(_)
+        | Data::CacheStats(_)
+        | Data::Config(_)
+        | Data::Calibration(_)
+        | Data::Reading(_) => true,
+        _ => false,
+    }
+}
+
+/// The first message that `wanted`, the others are dropped.
+fn wait(station: &mut Station, wanted: impl Fn(&Data) -> bool) -> Result<Message> {
+    let deadline = Instant::now() + TIMEOUT;
+    while let Some(msg) = station.receive(deadline)? {
+        if wanted(&msg.data) {
+            return Ok(msg);
+        }
+    }
+    Err("the node did not answer".into())
+}
+
+fn print(msg: &Message) -> Result<()> {
+    let mut line = String::new();
+    schema::json::write(&mut line, msg).map_err(|err| format!("{:?}", err))?;
+    println!("{}", line);
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Pairing with a node
+//!
+//! The station runs the same `Session` as the node, with its keys held in memory instead of the
+//! EEPROM: swap public keys, swap session keys, the lower key gives way. The key both ends settle
+//! on is what `pair` returns, the other subcommands seal with it.
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::crypt::{self, RSAKeyPair, RSAPublicKey};
+use crate::messages::{Data, Status};
+use crate::session::{self, Action, Session, State};
+use crate::station::Station;
+
+/// How long to keep answering after our side is established, the node may still be one exchange
+/// behind.
+const SETTLE_MS: u32 = 2 * session::TIMEOUT_MS;
+
+struct Keys {
+    /// The same toy pair the firmware provisions.
+    pair: RSAKeyPair,
+    own: [u8; 8],
+    peer_public: Option<RSAPublicKey>,
+}
+
+/// Run the key exchange until both ends hold the same session key, or it fails.
+pub fn pair(station: &mut Station) -> io::Result<[u8; 8]> {
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos())
+        ^ std::process::id() as u128;
+    let mut keys = Keys {
+        pair: crypt::PROVISIONED,
+        own: crypt::generate_aes_key(&seed.to_le_bytes()),
+        peer_public: None,
+    };
+    let mut session = Session::new();
+    station.set_session_key(None);
+
+    let action = session.start(station.now(), false, false);
+    perform(station, &session, &keys, action)?;
+    let mut established_at = None;
+    loop {
+        let msg = station.receive(Instant::now() + Duration::from_millis(50))?;
+        let now = station.now();
+        let action = match msg.map(|msg| msg.data) {
+            Some(Data::RSAPublicKey(key)) => {
+                keys.peer_public = Some(key);
+                session.on_public_key(now)
+            }
+            Some(Data::Status(Status::UnkownPublicKey)) => session.on_public_key_requested(),
+            Some(Data::Status(Status::UnkownAESKey)) => session.on_session_key_requested(),
+            Some(Data::AESKey(key)) => {
+                let peer = crypt::decrypt(&keys.pair.private(), &key);
+                let action = session.on_session_key(now, keys.own.cmp(&peer));
+                if action == Action::AdoptPeerSessionKey {
+                    keys.own = peer;
+                }
+                action
+            }
+            _ => session.poll(now),
+        };
+        perform(station, &session, &keys, action)?;
+
+        match session.state() {
+            State::Established => {
+                let since = *established_at.get_or_insert(now);
+                if now.wrapping_sub(since) >= SETTLE_MS {
+                    return Ok(keys.own);
+                }
+            }
+            State::Failed => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the node did not answer the key exchange",
+                ))
+            }
+            _ => established_at = None,
+        }
+    }
+}
+
+/// What `Node::perform` does on the node.
+fn perform(
+    station: &mut Station,
+    session: &Session,
+    keys: &Keys,
+    action: Action,
+) -> io::Result<()> {
+    let msg = match action {
+        Action::Nothing | Action::AdoptPeerSessionKey => None,
+        Action::RequestPublicKey => Some(Data::Status(Status::UnkownPublicKey)),
+        Action::SendPublicKey => Some(Data::RSAPublicKey(keys.pair.public())),
+        Action::RequestSessionKey => Some(Data::Status(Status::UnkownAESKey)),
+        Action::SendSessionKey => keys
+            .peer_public
+            .as_ref()
+            .map(|peer| Data::AESKey(crypt::encrypt(peer, &keys.own))),
+    };
+    station.set_session_key(session.is_established().then_some(keys.own));
+    if let Some(msg) = msg {
+        station.send(&msg)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! The ground station's end of the link
+//!
+//! What `ComsManager` and the link setup do on the node, over a serial port. Messages go out
+//! through `wire`, sealed once there is a session key, and come back in through the node's own `Receiver`. The
+//! station answers the node's `Hello` itself and talks to it at the version they settle on.
+//!
+//! With a capture file, everything that crosses the line is written to it as it happens, see
+//! `capture`. Messages going out are recorded before they are sealed.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Instant;
+
+use serialport::SerialPort;
+
+use crate::capture::{self, Direction};
+use crate::coms_manager::Receiver;
+use crate::link::Link;
+use crate::messages::{Capabilities, Data, Encrypted, Message};
+use crate::schema;
+use crate::wire;
+
+#[cfg(test)]
+mod tests;
+
+/// What the station announces in its `Hello`, nodes take it for a peer without a sensor.
+pub const NODE_ID: u32 = 0x6753_0000;
+
+pub struct Station {
+    port: Box<dyn SerialPort>,
+    receiver: Receiver,
+    link: Link,
+    start: Instant,
+    packet_id: u8,
+    nonce: u32,
+    session_key: Option<[u8; 8]>,
+    /// Decoded, not yet handed out.
+    pending: VecDeque<Message>,
+    capture: Option<File>,
+}
+
+impl Station {
+    pub fn new(port: Box<dyn SerialPort>, session_key: Option<[u8; 8]>) -> Station {
+        let mut receiver = Receiver::new();
+        receiver.set_session_key(session_key.as_ref());
+        Station {
+            port,
+            receiver,
+            link: Link::new(NODE_ID, Capabilities::default()),
+            start: Instant::now(),
+            packet_id: 0,
+            nonce: 0,
+            session_key,
+            pending: VecDeque::new(),
+            capture: None,
+        }
+    }
+
+    /// Record the link to `file` from now on.
+    pub fn capture_to(&mut self, file: File) {
+        self.capture = Some(file);
+    }
+
+    /// Milliseconds since the station opened, the clock the link and the key exchange run on.
+    pub fn now(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+
+    /// Seal everything but the link setup under `key` from now on, and open what comes back with it.
+    pub fn set_session_key(&mut self, key: Option<[u8; 8]>) {
+        self.session_key = key;
+        self.receiver.set_session_key(key.as_ref());
+    }
+
+    /// Send `data`, returns the id it went out under.
+    pub fn send(&mut self, data: &Data) -> io::Result<u8> {
+        if data.since() > self.link.version() {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "the node's protocol version does not have this message",
+            ));
+        }
+        let id = self.packet_id;
+        let clear = Message {
+            id,
+            data: data.clone(),
+        };
+        let data = match &self.session_key {
+            Some(key) if !data.is_link_setup() => {
+                self.nonce = self.nonce.wrapping_add(1);
+                let sealed = Encrypted::seal(key, self.nonce, data)
+                    .map_err(|err| io::Error::other(format!("cannot seal: {:?}", err)))?;
+                Data::Encrypted(sealed)
+            }
+            _ => data.clone(),
+        };
+
+        let mut bytes = Vec::new();
+        wire::write(id, &data, self.link.version(), |byte| bytes.push(byte))
+            .map_err(|err| io::Error::other(format!("cannot frame: {:?}", err)))?;
+        self.port.write_all(&bytes)?;
+        self.record(|out, now| {
+            capture::write_bytes(out, now, Direction::Tx, &bytes)?;
+            capture::write_message(out, now, Direction::Tx, &clear)
+        })?;
+        self.packet_id = self.packet_id.wrapping_add(1);
+        Ok(id)
+    }
+
+    /// The next message from the node, `None` if nothing came in before `deadline`. A `Hello` is
+    /// answered here and still handed on, frames that do not decode are reported on stderr.
+    pub fn receive(&mut self, deadline: Instant) -> io::Result<Option<Message>> {
+        loop {
+            if let Some(msg) = self.pending.pop_front() {
+                if let Data::Hello(hello) = &msg.data {
+                    let reply = self.link.on_hello(self.now(), hello.clone());
+                    if let Some(status) = reply.status {
+                        self.send(&Data::Status(status))?;
+                    }
+                    if let Some(hello) = reply.hello {
+                        self.send(&Data::Hello(hello))?;
+                    }
+                }
+                if self.link.accepts(&msg.data) {
+                    return Ok(Some(msg));
+                }
+                continue;
+            }
+
+            // announce ourselves until the node has answered.
+            if let Some(hello) = self.link.poll(self.now()) {
+                self.send(&Data::Hello(hello))?;
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+
+            let mut buf = [0; 256];
+            let len = match self.port.read(&mut buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == ErrorKind::TimedOut => 0,
+                Err(err) => return Err(err),
+            };
+            if len == 0 {
+                continue;
+            }
+            let now = self.now();
+            let decoded = self.pending.len();
+            let mut dropped = Vec::new();
+            for &byte in &buf[..len] {
+                self.receiver.push(byte);
+                if !self.receiver.has_new_message() {
+                    continue;
+                }
+                while let Some(result) = self.receiver.next(now) {
+                    match result {
+                        Ok(msg) => self.pending.push_back(msg),
+                        Err(err) => dropped.push(err),
+                    }
+                }
+            }
+            for err in &dropped {
+                eprintln!("dropped a frame: {:?}", err);
+            }
+
+            let pending = &self.pending;
+            let capture = &mut self.capture;
+            record(capture, |out| {
+                capture::write_bytes(out, now, Direction::Rx, &buf[..len])?;
+                for err in &dropped {
+                    writeln!(out, "# dropped a frame: {:?}", err)?;
+                }
+                for msg in pending.range(decoded..) {
+                    capture::write_message(out, now, Direction::Rx, msg)?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    fn record(
+        &mut self,
+        f: impl FnOnce(&mut String, u32) -> Result<(), schema::Error>,
+    ) -> io::Result<()> {
+        let now = self.now();
+        record(&mut self.capture, |out| f(out, now))
+    }
+}
+
+/// Write what `f` records to the capture, if there is one. Each batch goes out as soon as it is
+/// complete, so a capture is good up to the moment the station stopped.
+fn record(
+    capture: &mut Option<File>,
+    f: impl FnOnce(&mut String) -> Result<(), schema::Error>,
+) -> io::Result<()> {
+    let Some(file) = capture else {
+        return Ok(());
+    };
+    let mut text = String::new();
+    f(&mut text).map_err(|err| io::Error::other(format!("cannot record: {:?}", err)))?;
+    file.write_all(text.as_bytes())
+}
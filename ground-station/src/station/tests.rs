@@ -0,0 +1,187 @@
+use std::thread;
+use std::time::Duration;
+
+use serialport::TTYPort;
+
+use super::*;
+use crate::messages::{Command, Hello, Status, Temperature, PROTOCOL_VERSION};
+use crate::pair;
+use crate::session::sim::Node;
+
+const KEY: [u8; 8] = [0x3C, 0x11, 0x7E, 0x42, 0x09, 0x5D, 0x60, 0x24];
+
+/// A station on one end of a pseudo terminal, and the other end for the test to play the node.
+fn station(key: Option<[u8; 8]>) -> (Station, TTYPort) {
+    let (mut ours, mut theirs) = TTYPort::pair().unwrap();
+    ours.set_timeout(Duration::from_millis(20)).unwrap();
+    theirs.set_timeout(Duration::from_millis(20)).unwrap();
+    (Station::new(Box::new(ours), key), theirs)
+}
+
+fn send(port: &mut TTYPort, id: u8, data: &Data) {
+    let mut bytes = Vec::new();
+    wire::write(id, data, PROTOCOL_VERSION, |byte| bytes.push(byte)).unwrap();
+    port.write_all(&bytes).unwrap();
+}
+
+/// Everything the station sent that is not a `Hello`, as the node receives it.
+fn receive(port: &mut TTYPort, receiver: &mut Receiver, count: usize) -> Vec<Message> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut received = Vec::new();
+    while received.len() < count && Instant::now() < deadline {
+        let mut buf = [0; 64];
+        let len = match port.read(&mut buf) {
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::TimedOut => 0,
+            Err(err) => panic!("{err}"),
+        };
+        for &byte in &buf[..len] {
+            receiver.push(byte);
+            while let Some(msg) = receiver.receive(0) {
+                if !matches!(msg.data, Data::Hello(_)) {
+                    received.push(msg);
+                }
+            }
+        }
+    }
+    received
+}
+
+fn encode(data: &Data) -> heapless::Vec<u8, 64> {
+    postcard::to_vec(data).unwrap()
+}
+
+fn soon() -> Instant {
+    Instant::now() + Duration::from_secs(2)
+}
+
+#[test]
+fn messages_are_sealed_once_there_is_a_session_key() {
+    let (mut station, mut node) = station(None);
+    let command = Data::Command(Command::ReportWear);
+    let request = Data::Status(Status::UnkownAESKey);
+    station.send(&command).unwrap();
+    station.set_session_key(Some(KEY));
+    station.send(&command).unwrap();
+    station.send(&request).unwrap();
+
+    // a node without the key sees what went out sealed as it is.
+    let received = receive(&mut node, &mut Receiver::new(), 3);
+    assert_eq!(received.len(), 3);
+    assert_eq!(encode(&received[0].data), encode(&command));
+    assert!(matches!(received[1].data, Data::Encrypted(_)));
+    assert_eq!(encode(&received[2].data), encode(&request));
+
+    let mut keyed = Receiver::new();
+    keyed.set_session_key(Some(&KEY));
+    station.send(&command).unwrap();
+    let received = receive(&mut node, &mut keyed, 1);
+    assert_eq!(encode(&received[0].data), encode(&command));
+}
+
+#[test]
+fn back_to_back_messages_come_out_in_order() {
+    let (mut station, mut node) = station(None);
+    let mut bytes = Vec::new();
+    for temp in [1.0, 2.0, 3.0] {
+        let data = Data::Temperature(Temperature { temp });
+        wire::write(temp as u8, &data, PROTOCOL_VERSION, |byte| bytes.push(byte)).unwrap();
+    }
+    node.write_all(&bytes).unwrap();
+    for id in 1..=3 {
+        let msg = station.receive(soon()).unwrap().unwrap();
+        assert_eq!(msg.id, id);
+        assert!(matches!(msg.data, Data::Temperature(Temperature { temp }) if temp == id as f32));
+    }
+}
+
+#[test]
+fn a_hello_is_answered_and_handed_on() {
+    let (mut station, mut node) = station(None);
+    // old enough to be turned down.
+    let hello = Hello {
+        protocol_version: 0,
+        capabilities: Capabilities::default(),
+        node_id: 7,
+    };
+    send(&mut node, 0, &Data::Hello(hello));
+    let msg = station.receive(soon()).unwrap().unwrap();
+    assert!(matches!(msg.data, Data::Hello(Hello { node_id: 7, .. })));
+    let received = receive(&mut node, &mut Receiver::new(), 1);
+    assert!(matches!(
+        received[..],
+        [Message {
+            data: Data::Status(Status::Incompatible { .. }),
+            ..
+        }]
+    ));
+
+    // nothing but another `Hello` gets through from a rejected node.
+    send(&mut node, 1, &Data::Temperature(Temperature { temp: 1.0 }));
+    let deadline = Instant::now() + Duration::from_millis(200);
+    assert!(station.receive(deadline).unwrap().is_none());
+}
+
+#[test]
+fn the_capture_replays_to_what_was_recorded() {
+    let path = std::env::temp_dir().join(format!("station-{}.capture", std::process::id()));
+    let (mut station, mut node) = station(None);
+    station.capture_to(File::create(&path).unwrap());
+    station.send(&Data::Command(Command::GetConfig)).unwrap();
+    send(&mut node, 5, &Data::Temperature(Temperature { temp: 20.0 }));
+    station.receive(soon()).unwrap().unwrap();
+    drop(station);
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(capture::replay::verify(&text, Direction::Rx, None), Ok(1));
+    // the station's own `Hello` went out first.
+    assert_eq!(capture::replay::verify(&text, Direction::Tx, None), Ok(2));
+}
+
+#[test]
+fn pairing_settles_on_the_key_the_node_holds() {
+    let (mut station, mut port) = station(None);
+    let node = thread::spawn(move || {
+        let start = Instant::now();
+        let now = || start.elapsed().as_millis() as u32;
+        let mut node = Node::new([0x01; 8]);
+        let mut receiver = Receiver::new();
+        let mut id = 0u8;
+        let mut out = |data: Option<Data>, port: &mut TTYPort| {
+            if let Some(data) = data {
+                send(port, id, &data);
+                id = id.wrapping_add(1);
+            }
+        };
+        let action = node.session.start(now(), false, false);
+        out(node.perform(action), &mut port);
+        // the station settles for two timeouts after it is established, then sends a command.
+        while start.elapsed() < Duration::from_secs(10) {
+            let mut buf = [0; 64];
+            let len = port.read(&mut buf).unwrap_or(0);
+            for &byte in &buf[..len] {
+                receiver.push(byte);
+                while let Some(msg) = receiver.receive(now()) {
+                    if let Data::Command(command) = msg.data {
+                        return (node.own_key, Some(command));
+                    }
+                    let action = node.receive(now(), &msg.data);
+                    out(node.perform(action), &mut port);
+                    if node.session.is_established() {
+                        receiver.set_session_key(Some(&node.own_key));
+                    }
+                }
+            }
+            let action = node.session.poll(now());
+            out(node.perform(action), &mut port);
+        }
+        (node.own_key, None)
+    });
+
+    let key = pair::pair(&mut station).unwrap();
+    station.send(&Data::Command(Command::SelfTest)).unwrap();
+    let (node_key, command) = node.join().unwrap();
+    assert_eq!(key, node_key);
+    assert!(matches!(command, Some(Command::SelfTest)));
+}
@@ -0,0 +1,214 @@
+use std::io::{ErrorKind, Read, Write};
+use std::thread;
+
+use serialport::{SerialPort, TTYPort};
+
+use super::*;
+use coms_manager::Receiver;
+use key_store::Slot;
+use messages::{CommandId, Encrypted, Reply, Response, WipeReport};
+
+const KEY: [u8; 8] = [0x3C, 0x11, 0x7E, 0x42, 0x09, 0x5D, 0x60, 0x24];
+
+fn parsed(line: &str) -> Result<Args> {
+    parse(line.split_whitespace().map(String::from))
+}
+
+fn error(line: &str) -> String {
+    parsed(line).err().unwrap().to_string()
+}
+
+#[test]
+fn a_device_and_a_command_take_the_defaults() {
+    let args = parsed("/dev/ttyUSB0 command self-test").unwrap();
+    assert_eq!(args.device.as_deref(), Some("/dev/ttyUSB0"));
+    assert_eq!(args.baud, config::Config::default().baud_rate);
+    assert_eq!(args.key, PathBuf::from("session.key"));
+    assert_eq!(args.capture, None);
+    assert_eq!(args.command, ["command", "self-test"]);
+}
+
+#[test]
+fn options_come_before_the_device() {
+    let args = parsed("--baud 9600 --key node.key --capture link.txt /dev/ttyACM1 pair").unwrap();
+    assert_eq!(args.device.as_deref(), Some("/dev/ttyACM1"));
+    assert_eq!(args.baud, 9600);
+    assert_eq!(args.key, PathBuf::from("node.key"));
+    assert_eq!(args.capture, Some(PathBuf::from("link.txt")));
+    assert_eq!(args.command, ["pair"]);
+}
+
+#[test]
+fn a_pseudo_terminal_and_a_replay_need_no_device() {
+    let args = parsed("--pty telemetry").unwrap();
+    assert_eq!(args.device, None);
+    assert_eq!(args.command, ["telemetry"]);
+
+    let args = parsed("--key node.key replay link.txt rx").unwrap();
+    assert_eq!(args.device, None);
+    assert_eq!(args.command, ["replay", "link.txt", "rx"]);
+}
+
+#[test]
+fn bad_arguments_are_turned_down() {
+    assert_eq!(error("/dev/ttyUSB0"), "no command given");
+    assert_eq!(
+        error("--verbose /dev/ttyUSB0 pair"),
+        "unknown option --verbose"
+    );
+    assert_eq!(error("--baud"), "--baud needs a rate");
+    assert_eq!(error("--key"), "--key needs a file");
+    assert_eq!(error("--capture"), "--capture needs a file");
+    assert!(parsed("--baud fast /dev/ttyUSB0 pair").is_err());
+    assert!(parsed("--help").is_err());
+}
+
+#[test]
+fn every_command_name_parses() {
+    let names = [
+        ("delete-aes-key", CommandId::DeleteAESKey),
+        ("wipe-all-keys", CommandId::WipeAllKeys),
+        ("report-wear", CommandId::ReportWear),
+        ("report-cache-stats", CommandId::ReportCacheStats),
+        ("get-config", CommandId::GetConfig),
+        ("get-calibration", CommandId::GetCalibration),
+        ("read-temperature", CommandId::ReadTemperature),
+        ("self-test", CommandId::SelfTest),
+    ];
+    for (name, id) in names {
+        assert_eq!(parse_command(&[name]).unwrap().id(), id);
+    }
+}
+
+#[test]
+fn set_calibration_takes_two_points() {
+    let command = parse_command(&["set-calibration", "20", "18.5", "-80", "83.25"]).unwrap();
+    let Command::SetCalibration(calibration) = command else {
+        panic!("{command:?}");
+    };
+    assert_eq!(calibration.low.measured, 2000);
+    assert_eq!(calibration.low.actual, 1850);
+    assert_eq!(calibration.high.measured, -8000);
+    assert_eq!(calibration.high.actual, 8325);
+
+    assert!(parse_command(&["set-calibration", "20", "18", "80"]).is_err());
+    assert!(parse_command(&["reboot"]).is_err());
+}
+
+#[test]
+fn calibration_temperatures_are_taken_in_celsius() {
+    assert_eq!(hundredths("21.004").unwrap(), 2100);
+    assert_eq!(hundredths("-0.006").unwrap(), -1);
+    assert_eq!(hundredths("327.67").unwrap(), i16::MAX);
+    assert_eq!(hundredths("-327.68").unwrap(), i16::MIN);
+
+    for text in ["327.68", "-327.69", "1e9", "NaN", "inf", "20 °C", ""] {
+        assert!(hundredths(text).is_err(), "{text}");
+    }
+}
+
+#[test]
+fn a_saved_key_loads_back() {
+    let path = env::temp_dir().join(format!("ground-station-{}.key", std::process::id()));
+    let key = [0x00, 0x01, 0x7F, 0x80, 0xAB, 0xCD, 0xEF, 0xFF];
+    fs::write(&path, hex(&key)).unwrap();
+    assert_eq!(load_key(&path).unwrap(), Some(key));
+
+    fs::write(&path, "0001").unwrap();
+    assert!(load_key(&path).is_err());
+    fs::write(&path, "000102030405060g").unwrap();
+    assert!(load_key(&path).is_err());
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(load_key(&path).unwrap(), None);
+}
+
+#[test]
+fn only_the_answer_to_our_command_is_waited_for() {
+    let response = |request_id| {
+        Data::Response(messages::Response {
+            request_id,
+            command: CommandId::SelfTest,
+            result: Err(messages::ErrorCode::Busy),
+        })
+    };
+    assert!(answers(&response(3), 3));
+    assert!(!answers(&response(4), 3));
+    assert!(answers(&Data::Status(Status::LogDumped(0)), 3));
+    assert!(!answers(&Data::Status(Status::UnkownAESKey), 3));
+}
+
+/// Plays a node that answers the first command the way the firmware answers `delete-aes-key`: the
+/// response goes out sealed under the session key, then the key is dropped and a new one asked for.
+/// Hands back whether it answered, and the port so the line stays open.
+fn node_dropping_its_key(mut port: TTYPort) -> thread::JoinHandle<(bool, TTYPort)> {
+    thread::spawn(move || {
+        let mut receiver = Receiver::new();
+        receiver.set_session_key(Some(&KEY));
+        let write = |port: &mut TTYPort, id: u8, data: &Data| {
+            let mut bytes = Vec::new();
+            wire::write(id, data, messages::PROTOCOL_VERSION, |byte| {
+                bytes.push(byte)
+            })
+            .unwrap();
+            port.write_all(&bytes).unwrap();
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            let mut buf = [0; 64];
+            let len = match port.read(&mut buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == ErrorKind::TimedOut => 0,
+                Err(err) => panic!("{err}"),
+            };
+            for &byte in &buf[..len] {
+                receiver.push(byte);
+                while let Some(msg) = receiver.receive(0) {
+                    let Data::Command(command) = msg.data else {
+                        continue;
+                    };
+                    let response = Data::Response(Response {
+                        request_id: msg.id,
+                        command: command.id(),
+                        result: Ok(Reply::KeysWiped(WipeReport {
+                            wiped: Slot::OwnAesKey.mask(),
+                            failed: 0,
+                        })),
+                    });
+                    let sealed = Encrypted::seal(&KEY, 1, &response).unwrap();
+                    write(&mut port, 0, &Data::Encrypted(sealed));
+                    receiver.set_session_key(None);
+                    write(&mut port, 1, &Data::Status(Status::UnkownAESKey));
+                    return (true, port);
+                }
+            }
+        }
+        (false, port)
+    })
+}
+
+#[test]
+fn delete_aes_key_is_answered_before_the_key_goes() {
+    let (mut ours, mut theirs) = TTYPort::pair().unwrap();
+    ours.set_timeout(Duration::from_millis(20)).unwrap();
+    theirs.set_timeout(Duration::from_millis(20)).unwrap();
+    let node = node_dropping_its_key(theirs);
+    let mut station = Station::new(Box::new(ours), Some(KEY));
+
+    let command = parse_command(&["delete-aes-key"]).unwrap();
+    let id = station.send(&Data::Command(command)).unwrap();
+    let msg = wait(&mut station, |data| answers(data, id)).unwrap();
+    let (answered, _port) = node.join().unwrap();
+    assert!(answered);
+    let Data::Response(response) = msg.data else {
+        panic!("{:?}", msg.data);
+    };
+    assert_eq!(response.command, CommandId::DeleteAESKey);
+    assert!(matches!(
+        response.result,
+        Ok(Reply::KeysWiped(WipeReport { failed: 0, .. }))
+    ));
+    // the request for a new key that follows still comes in the clear.
+    let msg = wait(&mut station, |data| !matches!(data, Data::Hello(_))).unwrap();
+    assert!(matches!(msg.data, Data::Status(Status::UnkownAESKey)));
+}
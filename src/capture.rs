@@ -0,0 +1,157 @@
+//! Link captures
+//!
+//! A record of what crossed the serial line, as text, one record per line in the order things
+//! happened:
+//!
+//! ```text
+//! 1520 rx bytes 0303010100ff
+//! 1520 rx message {"id":3,"data":{"Status":"UnkownPublicKey"}}
+//! 1533 tx bytes 0402...
+//! ```
+//!
+//! Each record has the milliseconds since the capture started, the direction as seen by whoever
+//! recorded it, and either raw bytes in hex, exactly as they crossed the line, or a message decoded
+//! from them as JSON, see `schema::json`. Sealed messages are written opened when the recorder held
+//! the session key. Only the bytes are needed to replay a capture, the messages are there to be
+//! read and to hold a replay against. Lines that start with `#` are comments.
+
+use core::fmt::Write;
+
+use crate::messages::Message;
+use crate::schema::{self, json};
+
+pub mod replay;
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Came in to the recorder.
+    Rx,
+    /// Went out from the recorder.
+    Tx,
+}
+
+impl Direction {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Direction::Rx => "rx",
+            Direction::Tx => "tx",
+        }
+    }
+
+    pub const fn reverse(self) -> Direction {
+        match self {
+            Direction::Rx => Direction::Tx,
+            Direction::Tx => Direction::Rx,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The line does not start with a timestamp.
+    Millis,
+    /// Neither `rx` nor `tx`.
+    Direction,
+    /// Neither `bytes` nor `message`.
+    Kind,
+    /// Bytes that are not pairs of hex digits.
+    Hex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry<'a> {
+    /// Hex digits, checked when the line was parsed.
+    Bytes(&'a str),
+    /// A message as JSON, as it was written.
+    Message(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub millis: u32,
+    pub direction: Direction,
+    pub entry: Entry<'a>,
+}
+
+impl<'a> Record<'a> {
+    /// `None` for a blank line or a comment.
+    pub fn parse(line: &'a str) -> Option<Result<Record<'a>, Error>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        Some(Record::fields(line))
+    }
+
+    fn fields(line: &'a str) -> Result<Record<'a>, Error> {
+        let mut fields = line.splitn(4, ' ');
+        let millis = fields.next().and_then(|millis| millis.parse().ok());
+        let millis = millis.ok_or(Error::Millis)?;
+        let direction = match fields.next() {
+            Some("rx") => Direction::Rx,
+            Some("tx") => Direction::Tx,
+            _ => return Err(Error::Direction),
+        };
+        let entry = match (fields.next(), fields.next()) {
+            (Some("bytes"), Some(hex)) => Entry::Bytes(hex),
+            (Some("message"), Some(json)) => Entry::Message(json),
+            _ => return Err(Error::Kind),
+        };
+        if let Entry::Bytes(hex) = entry {
+            let digits = hex.bytes().filter(|c| *c != b' ');
+            if digits.clone().any(|c| !c.is_ascii_hexdigit()) || digits.count() % 2 != 0 {
+                return Err(Error::Hex);
+            }
+        }
+        Ok(Record {
+            millis,
+            direction,
+            entry,
+        })
+    }
+
+    /// The bytes of a `Bytes` record, nothing for a message.
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + 'a {
+        let hex = match self.entry {
+            Entry::Bytes(hex) => hex.as_bytes(),
+            Entry::Message(_) => &[],
+        };
+        let mut digits = hex.iter().filter(|c| **c != b' ').map(|c| digit(*c));
+        core::iter::from_fn(move || Some(digits.next()? << 4 | digits.next()?))
+    }
+}
+
+fn digit(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        _ => c - b'A' + 10,
+    }
+}
+
+pub fn write_bytes<W: Write>(
+    out: &mut W,
+    millis: u32,
+    direction: Direction,
+    bytes: &[u8],
+) -> core::fmt::Result {
+    write!(out, "{} {} bytes ", millis, direction.name())?;
+    for byte in bytes {
+        write!(out, "{:02x}", byte)?;
+    }
+    out.write_char('\n')
+}
+
+pub fn write_message<W: Write>(
+    out: &mut W,
+    millis: u32,
+    direction: Direction,
+    msg: &Message,
+) -> Result<(), schema::Error> {
+    write!(out, "{} {} message ", millis, direction.name())?;
+    json::write(out, msg)?;
+    out.write_char('\n')?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Replaying a capture through the board's receive path
+//!
+//! `replay` pushes the bytes of one direction of a capture into a `Receiver` one at a time, the way
+//! the USART interrupt does, and calls `receive` whenever it has a message waiting, the way the
+//! main loop does. The capture's timestamps are the clock, so a capture gives the same messages
+//! every time, bugs and all.
+//!
+//! To see what the node made of a capture the ground station recorded, replay its `tx` side.
+
+use heapless::String;
+
+use super::{Direction, Entry, Error, Record};
+use crate::coms_manager::Receiver;
+use crate::messages::Message;
+use crate::schema::json;
+
+/// Where a replay went wrong, with the line of the capture, counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The line is not a record.
+    Parse(usize, Error),
+    /// The replay gave a different message than the one recorded here.
+    Mismatch(usize),
+    /// The message recorded here never came out of the replay.
+    Missing(usize),
+    /// The replay gave a message past the last one recorded.
+    Extra,
+}
+
+/// Feed the bytes going `direction` through a fresh `Receiver`, opening sealed messages with `key`.
+/// `f` gets every message with the time it came out.
+pub fn replay<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+    direction: Direction,
+    key: Option<&[u8; 8]>,
+    mut f: impl FnMut(u32, Message),
+) -> Result<(), Failure> {
+    let mut receiver = Receiver::new();
+    receiver.set_session_key(key);
+    for (i, line) in lines.into_iter().enumerate() {
+        let record = match Record::parse(line) {
+            None => continue,
+            Some(record) => record.map_err(|err| Failure::Parse(i + 1, err))?,
+        };
+        if record.direction != direction {
+            continue;
+        }
+        for byte in record.bytes() {
+            receiver.push(byte);
+            if receiver.has_new_message() {
+                if let Some(msg) = receiver.receive(record.millis) {
+                    f(record.millis, msg);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replay `direction` and hold every message against the `message` records of that direction, in
+/// order. Returns how many matched.
+pub fn verify(
+    capture: &str,
+    direction: Direction,
+    key: Option<&[u8; 8]>,
+) -> Result<usize, Failure> {
+    let mut recorded =
+        capture
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| match Record::parse(line) {
+                Some(Ok(Record {
+                    direction: d,
+                    entry: Entry::Message(json),
+                    ..
+                })) if d == direction => Some((i + 1, json)),
+                _ => None,
+            });
+
+    let mut matched = 0;
+    let mut failure = None;
+    replay(capture.lines(), direction, key, |_, msg| {
+        if failure.is_some() {
+            return;
+        }
+        let mut text = String::<1024>::new();
+        let written = json::write(&mut text, &msg).is_ok();
+        failure = match recorded.next() {
+            Some((_, json)) if written && text == json => {
+                matched += 1;
+                None
+            }
+            Some((line, _)) => Some(Failure::Mismatch(line)),
+            None => Some(Failure::Extra),
+        };
+    })?;
+
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+    match recorded.next() {
+        Some((line, _)) => Err(Failure::Missing(line)),
+        None => Ok(matched),
+    }
+}
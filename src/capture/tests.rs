@@ -0,0 +1,147 @@
+use heapless::{String, Vec};
+
+use super::replay::{self, verify};
+use super::*;
+use crate::messages::{Command, Data, Encrypted, Status, PROTOCOL_VERSION};
+use crate::session::sim::{self, Node};
+
+const KEY: [u8; 8] = [0x3a, 0x91, 0x07, 0xc4, 0x5e, 0x22, 0xf0, 0x68];
+
+fn messages() -> [Data; 4] {
+    [
+        Data::Status(Status::UnkownPublicKey),
+        Data::Command(Command::SelfTest),
+        Data::Command(Command::ReadTemperature),
+        Data::Status(Status::SensorFailed),
+    ]
+}
+
+/// The `message` records going `direction`.
+fn recorded(capture: &str, direction: Direction) -> usize {
+    capture
+        .lines()
+        .filter(|line| {
+            matches!(
+                Record::parse(line),
+                Some(Ok(Record {
+                    direction: d,
+                    entry: Entry::Message(_),
+                    ..
+                })) if d == direction
+            )
+        })
+        .count()
+}
+
+#[test]
+fn bytes_read_back_as_written() {
+    let bytes = [0x00, 0x01, 0x7f, 0xfe, 0xff];
+    let mut text = String::<64>::new();
+    write_bytes(&mut text, 4_000_000_000, Direction::Rx, &bytes).unwrap();
+    let record = Record::parse(&text).unwrap().unwrap();
+    assert_eq!(record.millis, 4_000_000_000);
+    assert_eq!(record.direction, Direction::Rx);
+    assert!(record.bytes().eq(bytes.iter().copied()));
+}
+
+#[test]
+fn a_message_reads_back_as_written() {
+    let msg = Message {
+        id: 9,
+        data: Data::Command(Command::GetConfig),
+    };
+    let mut text = String::<128>::new();
+    write_message(&mut text, 12, Direction::Tx, &msg).unwrap();
+    assert!(text.ends_with('\n'));
+    let Some(Ok(Record {
+        millis: 12,
+        direction: Direction::Tx,
+        entry: Entry::Message(json),
+    })) = Record::parse(&text)
+    else {
+        panic!("{text}");
+    };
+    assert!(text.contains(json));
+}
+
+#[test]
+fn malformed_lines_are_turned_down() {
+    let rejected = [
+        ("rx bytes 00", Error::Millis),
+        ("-1 rx bytes 00", Error::Millis),
+        ("10 up bytes 00", Error::Direction),
+        ("10 rx frame 00", Error::Kind),
+        ("10 rx bytes", Error::Kind),
+        ("10 rx bytes 0", Error::Hex),
+        ("10 rx bytes 0g", Error::Hex),
+    ];
+    for (line, error) in rejected {
+        assert_eq!(Record::parse(line), Some(Err(error)), "{line}");
+    }
+    for line in ["", "   ", "# a comment"] {
+        assert_eq!(Record::parse(line), None, "{line}");
+    }
+}
+
+/// Each message is captured going out, in the clear and sealed, and replays to what was recorded.
+#[test]
+fn single_messages_replay_to_what_was_recorded() {
+    for (i, data) in messages().iter().enumerate() {
+        for key in [None, Some(&KEY)] {
+            let msg = Message {
+                id: i as u8,
+                data: data.clone(),
+            };
+            let sent = match key {
+                Some(key) => Data::Encrypted(Encrypted::seal(key, i as u32, data).unwrap()),
+                None => data.clone(),
+            };
+            let mut bytes = Vec::<u8, 256>::new();
+            crate::wire::write(msg.id, &sent, PROTOCOL_VERSION, |byte| {
+                bytes.push(byte).unwrap()
+            })
+            .unwrap();
+
+            let mut text = String::<1024>::new();
+            text.push_str("# one message\n").unwrap();
+            write_bytes(&mut text, 100, Direction::Tx, &bytes).unwrap();
+            write_message(&mut text, 100, Direction::Tx, &msg).unwrap();
+            assert_eq!(verify(&text, Direction::Tx, key), Ok(1), "message {i}");
+            // nothing went the other way.
+            assert_eq!(verify(&text, Direction::Rx, key), Ok(0), "message {i}");
+        }
+    }
+}
+
+#[test]
+fn a_different_message_fails_the_replay() {
+    let mut bytes = Vec::<u8, 64>::new();
+    let data = Data::Command(Command::SelfTest);
+    crate::wire::write(1, &data, PROTOCOL_VERSION, |byte| bytes.push(byte).unwrap()).unwrap();
+    let other = Message {
+        id: 1,
+        data: Data::Command(Command::ReadTemperature),
+    };
+    let mut text = String::<256>::new();
+    write_bytes(&mut text, 0, Direction::Rx, &bytes).unwrap();
+    write_message(&mut text, 0, Direction::Rx, &other).unwrap();
+    write_message(&mut text, 0, Direction::Rx, &other).unwrap();
+    assert_eq!(
+        verify(&text, Direction::Rx, None),
+        Err(replay::Failure::Mismatch(2))
+    );
+}
+
+/// A key exchange with every fourth message lost replays to every message recorded, both ways.
+#[test]
+fn a_simulated_key_exchange_replays_to_what_was_recorded() {
+    let mut nodes = [Node::new([0x11; 8]), Node::new([0x22; 8])];
+    let mut text = String::<8192>::new();
+    let paired = sim::capture(&mut nodes, 4, 5_000, &mut text).unwrap();
+    assert!(paired.is_some());
+    for direction in [Direction::Rx, Direction::Tx] {
+        let messages = recorded(&text, direction);
+        assert!(messages > 1, "{text}");
+        assert_eq!(verify(&text, direction, None), Ok(messages), "{text}");
+    }
+}
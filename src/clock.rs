@@ -0,0 +1,33 @@
+//! Millisecond clock driven by SysTick
+//!
+//! Counts from boot, so it restarts on every reset. Wraps after about 49 days.
+
+use core::cell::Cell;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+use cortex_m_rt::exception;
+
+// The M0 has no atomic read-modify-write, so the counter sits behind a critical section.
+static MILLIS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Start ticking once per millisecond. `sysclk_hz` is the core clock SysTick runs from.
+pub fn start(mut syst: SYST, sysclk_hz: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk_hz / 1_000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+/// Milliseconds since `start`.
+pub fn millis() -> u32 {
+    cortex_m::interrupt::free(|cs| MILLIS.borrow(cs).get())
+}
+
+#[exception]
+fn SysTick() {
+    cortex_m::interrupt::free(|cs| {
+        let millis = MILLIS.borrow(cs);
+        millis.set(millis.get().wrapping_add(1));
+    });
+}
@@ -0,0 +1,147 @@
+//! The commands this firmware serves
+//!
+//! One handler per `CommandId`, registered in `REGISTRY`. A handler runs to completion before its
+//! `Response` goes out. What it asks of the key exchange is left in `Node::after_response` and only
+//! carried out after that, so the response is still sealed under the key the command came in with.
+
+use crate::clock;
+use crate::eeprom::block;
+use crate::key_store::{OwnKeyPair, Slot};
+use crate::messages::{Command, CommandId, Data, ErrorCode, Event, Reading, Reply, SelfTestReport};
+use crate::node::{self, Node};
+use crate::rpc::Registry;
+use crate::session::State;
+use crate::telemetry;
+
+pub static REGISTRY: Registry<Node> = Registry::new(&[
+    (CommandId::DeleteAESKey, delete_aes_key),
+    (CommandId::WipeAllKeys, wipe_all_keys),
+    (CommandId::ReportWear, report_wear),
+    (CommandId::ReportCacheStats, report_cache_stats),
+    (CommandId::SetConfig, set_config),
+    (CommandId::GetConfig, get_config),
+    (CommandId::DumpLog, dump_log),
+    (CommandId::SetCalibration, set_calibration),
+    (CommandId::GetCalibration, get_calibration),
+    (CommandId::ReadTemperature, read_temperature),
+    (CommandId::SelfTest, self_test),
+]);
+
+fn delete_aes_key(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    // replacing the session key while it is being exchanged would only start the exchange over.
+    if matches!(
+        node.session.state(),
+        State::ExchangingSessionKey | State::Rekeying
+    ) {
+        return Err(ErrorCode::Busy);
+    }
+    let report = node.key_store.wipe_slots(&[Slot::OwnAesKey]);
+    node.log(&Event::KeysWiped(report.clone()));
+    // this drops the session key once the response is out, the peer cannot read anything sealed
+    // with it after that.
+    node.after_response = node.session.on_session_lost(clock::millis());
+    Ok(Reply::KeysWiped(report))
+}
+
+fn wipe_all_keys(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    let report = node.key_store.wipe_all();
+    node.log(&Event::KeysWiped(report.clone()));
+    // pair again from scratch once the keys are gone and the response is out.
+    node.after_response = node.session.start(clock::millis(), false, false);
+    Ok(Reply::KeysWiped(report))
+}
+
+/// Parts without room for the counts have nothing to report.
+fn report_wear(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    node.key_store
+        .storage()
+        .report()
+        .map(Reply::Wear)
+        .ok_or(ErrorCode::UnsupportedCommand)
+}
+
+fn report_cache_stats(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    Ok(Reply::CacheStats(node.key_store.cache_stats()))
+}
+
+/// Baud rate and PWM changes only take effect after a reset.
+fn set_config(node: &mut Node, command: Command) -> Result<Reply, ErrorCode> {
+    let Command::SetConfig(config) = command else {
+        return Err(ErrorCode::UnsupportedCommand);
+    };
+    match config.store(node.key_store.storage()) {
+        Ok(()) => {
+            node.sensor.set_sampling(config.sampling);
+            node.telemetry.set_period(telemetry::period(&config));
+            node.config = config;
+        }
+        Err(block::Error::Eeprom(_)) => return Err(ErrorCode::Eeprom),
+        // the new config does not fit, keep the old one.
+        Err(block::Error::TooLarge | block::Error::NoRoom) => return Err(ErrorCode::NoRoom),
+    }
+    Ok(Reply::Config(node.config.clone()))
+}
+
+fn get_config(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    Ok(Reply::Config(node.config.clone()))
+}
+
+/// Send every entry as a `Data::Log` of its own, the reply only counts them.
+fn dump_log(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    let Some(event_log) = &node.event_log else {
+        return Err(ErrorCode::UnsupportedCommand);
+    };
+    let mut sent = 0;
+    event_log.for_each(node.key_store.storage(), |entry| {
+        node::send(&Data::Log(entry));
+        sent += 1;
+    });
+    Ok(Reply::LogDumped(sent))
+}
+
+fn set_calibration(node: &mut Node, command: Command) -> Result<Reply, ErrorCode> {
+    let Command::SetCalibration(calibration) = command else {
+        return Err(ErrorCode::UnsupportedCommand);
+    };
+    match calibration.store(node.key_store.storage()) {
+        Ok(()) => node.sensor.set_calibration(calibration),
+        Err(block::Error::Eeprom(_)) => return Err(ErrorCode::Eeprom),
+        Err(block::Error::TooLarge | block::Error::NoRoom) => return Err(ErrorCode::NoRoom),
+    }
+    Ok(Reply::Calibration(node.sensor.calibration()))
+}
+
+fn get_calibration(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    Ok(Reply::Calibration(node.sensor.calibration()))
+}
+
+fn read_temperature(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    let measured = node
+        .sensor
+        .read_measured(&mut node.mux)
+        .map_err(|_| ErrorCode::SensorFailed)?;
+    Ok(Reply::Reading(Reading {
+        measured,
+        celsius: node.sensor.calibration().apply(measured),
+    }))
+}
+
+/// Runs every check, a failed one does not stop the others.
+fn self_test(node: &mut Node, _: Command) -> Result<Reply, ErrorCode> {
+    let mut report = SelfTestReport::default();
+    match node.mux.supply_mv() {
+        Some(supply_mv) => report.supply_mv = supply_mv,
+        None => report.failed |= SelfTestReport::SUPPLY,
+    }
+    if node.sensor.read_measured(&mut node.mux).is_err() {
+        report.failed |= SelfTestReport::SENSOR;
+    }
+    // also reads back the EEPROM, and the key wrapping with this MCU's key.
+    if !matches!(node.key_store.get(OwnKeyPair), Ok(Some(_))) {
+        report.failed |= SelfTestReport::IDENTITY;
+    }
+    if node::coms(|coms_manager| coms_manager.unauthentic()).unwrap_or(0) > 0 {
+        report.failed |= SelfTestReport::LINK;
+    }
+    Ok(Reply::SelfTest(report))
+}
@@ -1,21 +1,27 @@
 //! Communication module
 //!
 
+use crate::clock;
+use crate::crypt::Secret;
 use crate::messages::*;
+use crate::wire;
 
-use heapless::Vec;
-use postcard::accumulator::{CobsAccumulator, FeedResult};
 use stm32f0xx_hal::pac;
 use stm32f0xx_hal::prelude::*;
 use stm32f0xx_hal::serial::{Rx, Tx};
 
+mod receiver;
+
+pub use receiver::Receiver;
+
 pub struct ComsManager {
     packet_id: u8,
     transmitter: Tx<pac::USART1>,
     receiver: Rx<pac::USART1>,
-    buffer: Vec<u8, 256>,
-    cobs_buf: CobsAccumulator<256>,
-    new_message: bool,
+    incoming: Receiver,
+    session_key: Option<Secret<[u8; 8]>>,
+    nonce: u32,
+    peer_version: u16,
 }
 
 impl ComsManager {
@@ -24,95 +30,71 @@ impl ComsManager {
             packet_id: 0,
             transmitter,
             receiver,
-            buffer: Vec::new(),
-            cobs_buf: CobsAccumulator::new(),
-            new_message: false,
+            incoming: Receiver::new(),
+            session_key: None,
+            nonce: 0,
+            peer_version: PROTOCOL_VERSION,
         }
     }
 
-    pub fn has_new_message(&self) -> bool {
-        self.new_message
+    /// Sealed messages dropped since boot because they did not check out.
+    pub fn unauthentic(&self) -> u32 {
+        self.incoming.unauthentic()
     }
 
-    pub fn unstuff_message(stuffed: &[u8]) -> Vec<u8, 256> {
-        let mut unstuffed = Vec::<u8, 256>::new();
-        let mut escape = false;
+    /// Drop anything the peer's protocol version cannot decode from now on.
+    pub fn set_peer_version(&mut self, version: u16) {
+        self.peer_version = version;
+    }
 
-        for &byte in stuffed {
-            if escape {
-                unstuffed.push(byte).unwrap();
-                escape = false;
-            } else if byte == 0xFE {
-                escape = true;
-            } else {
-                unstuffed.push(byte).unwrap();
-            }
-        }
+    pub fn peer_version(&self) -> u16 {
+        self.peer_version
+    }
 
-        unstuffed
+    /// Seal everything but the link setup under `key` from now on, `None` goes back to sending
+    /// in the clear.
+    pub fn set_session_key(&mut self, key: Option<&[u8; 8]>) {
+        self.session_key = key.map(|key| Secret::new(*key));
+        self.incoming.set_session_key(key);
     }
 
-    pub fn send(&mut self, data: &Data) {
-        // Construct the message
-        let msg = Message {
-            id: self.packet_id,
-            data: data.clone(),
-        };
-        // Serialize the message
-        let mut buffer = [0; 32];
-        let payload = postcard::to_slice_cobs(&msg, &mut buffer).unwrap();
+    pub fn has_new_message(&self) -> bool {
+        self.incoming.has_new_message()
+    }
 
-        // Byte stuffing
-        let mut stuffed_payload = Vec::<u8, 64>::new();
-        for &mut byte in payload {
-            if byte == 0xFF {
-                stuffed_payload.push(0xFE).unwrap();
-            }
-            stuffed_payload.push(byte).unwrap();
+    pub fn send(&mut self, data: &Data) {
+        if data.since() > self.peer_version {
+            return;
         }
-        // Add stop frame
-        stuffed_payload.push(0xFF).unwrap();
+        let data = match &self.session_key {
+            Some(key) if !data.is_link_setup() => {
+                self.nonce = self.nonce.wrapping_add(1);
+                match Encrypted::seal(key, self.nonce, data) {
+                    Ok(sealed) => Data::Encrypted(sealed),
+                    // never fall back to the clear, a message that does not fit is dropped.
+                    Err(_) => return,
+                }
+            }
+            _ => data.clone(),
+        };
 
-        // Send the stuffed payload
-        for byte in stuffed_payload {
-            nb::block!(self.transmitter.write(byte)).unwrap();
-        }
+        // a message too large for one frame goes out in fragments under the same id, one too large
+        // for fragments is dropped.
+        let transmitter = &mut self.transmitter;
+        wire::write(self.packet_id, &data, self.peer_version, |byte| {
+            nb::block!(transmitter.write(byte)).unwrap()
+        })
+        .ok();
         self.packet_id = self.packet_id.wrapping_add(1);
     }
 
     pub fn read_byte(&mut self) {
-        let byte = nb::block!(self.receiver.read());
-        if let Ok(byte) = byte {
-            if self.buffer.push(byte).is_err() || byte == 0xFF {
-                self.new_message = true;
-            }
-            // let msg: FeedResult<'_, Message> = self.cobs_buf.feed::<Message>(&[byte]);
+        if let Ok(byte) = nb::block!(self.receiver.read()) {
+            self.incoming.push(byte);
         }
     }
 
     pub fn receive(&mut self) -> Option<Message> {
-        let mut msg = None;
-        let mut remaining_data: Vec<u8, 256> = Vec::new();
-
-        let buf = &mut self.buffer[..];
-        let mut window = &buf[..];
-
-        'cobs: while !window.is_empty() {
-            window = match self.cobs_buf.feed::<Message>(&window) {
-                FeedResult::Consumed => break 'cobs,
-                FeedResult::OverFull(new_wind) => new_wind,
-                FeedResult::DeserError(new_wind) => new_wind,
-                FeedResult::Success { data, remaining } => {
-                    msg = Some(data);
-                    remaining_data.extend_from_slice(remaining).unwrap();
-                    break 'cobs;
-                }
-            };
-        }
-
-        self.buffer.clear();
-        self.buffer.extend_from_slice(&remaining_data).unwrap();
-        self.new_message = false;
-        msg
+        self.incoming.receive(clock::millis())
     }
 }
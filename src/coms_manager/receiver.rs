@@ -0,0 +1,199 @@
+//! The receiving half of the link
+//!
+//! Bytes are pushed one at a time as the USART interrupt reads them, and `next` turns what has come
+//! in into messages, undoing what `wire::write` did: the 0xFE escapes and 0xFF stop bytes, COBS,
+//! fragmentation and, given the session key, the seal. There is no hardware in here, so the node,
+//! the ground station and the capture replay all receive through exactly this code.
+
+use heapless::{Deque, Vec};
+
+use crate::crypt::Secret;
+use crate::envelope;
+use crate::fragment::{Reassembler, FRAME_SIZE};
+use crate::messages::{Data, Message};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// More bytes than any frame has before the stop byte, they were dropped.
+    Overflow,
+    /// The frame did not decode into a `Message`.
+    Frame,
+    /// A fragmented message that came back together but did not decode.
+    Reassembled,
+    /// Sent in the clear while there is a session key, only the link setup may be.
+    Plaintext,
+    /// Sealed, but not under the session key or altered on the way. Anyone on the line can send
+    /// that, so it is dropped and only counted.
+    Unauthentic,
+}
+
+pub struct Receiver {
+    /// Bytes off the line that `next` has not looked at yet.
+    queue: Deque<u8, 256>,
+    /// Stop bytes in `queue`.
+    stops: usize,
+    /// The last byte pushed was an escape, so a 0xFF after it is data.
+    push_escape: bool,
+    /// The frame being unstuffed.
+    frame: Vec<u8, FRAME_SIZE>,
+    /// The last byte unstuffed was 0xFE, which escapes a 0xFF that is data.
+    escape: bool,
+    overflow: bool,
+    session_key: Option<Secret<[u8; 8]>>,
+    /// Messages turned down as `Unauthentic` since boot. Kept in RAM only, writing anything to the
+    /// EEPROM for them would let anyone on the line wear it out.
+    unauthentic: u32,
+    reassembler: Reassembler,
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Receiver::new()
+    }
+}
+
+impl Receiver {
+    pub fn new() -> Receiver {
+        Receiver {
+            queue: Deque::new(),
+            stops: 0,
+            push_escape: false,
+            frame: Vec::new(),
+            escape: false,
+            overflow: false,
+            session_key: None,
+            unauthentic: 0,
+            reassembler: Reassembler::new(),
+        }
+    }
+
+    /// Messages turned down as `Unauthentic` since boot.
+    pub fn unauthentic(&self) -> u32 {
+        self.unauthentic
+    }
+
+    /// Open sealed messages with `key` from now on, and turn down anything but the link setup that
+    /// was not sealed. A sealed message that checks out but does not decode is handed on as it is.
+    /// `None` hands every sealed message on as it is.
+    pub fn set_session_key(&mut self, key: Option<&[u8; 8]>) {
+        self.session_key = key.map(|key| Secret::new(*key));
+    }
+
+    /// True once a whole frame is in, or the queue is full.
+    pub fn has_new_message(&self) -> bool {
+        self.stops > 0 || self.queue.is_full()
+    }
+
+    /// One byte off the line. A byte that does not fit is lost, the frame it belonged to then
+    /// fails to decode.
+    pub fn push(&mut self, byte: u8) {
+        if self.queue.push_back(byte).is_err() {
+            return;
+        }
+        let escaped = core::mem::replace(&mut self.push_escape, byte == 0xFE);
+        if byte == 0xFF && !escaped {
+            self.stops += 1;
+        }
+    }
+
+    /// The next message, or why the next frame did not make one. `None` once every whole frame that
+    /// came in has been looked at. `now` times out fragments.
+    pub fn next(&mut self, now: u32) -> Option<Result<Message, Error>> {
+        while let Some(byte) = self.queue.pop_front() {
+            let escaped = core::mem::replace(&mut self.escape, false);
+            match byte {
+                0xFF if escaped => self.store(0xFF),
+                0xFF => {
+                    self.stops -= 1;
+                    if let Some(result) = self.end_frame(now) {
+                        return Some(result);
+                    }
+                }
+                0xFE => {
+                    // the escape is only known once the next byte is in.
+                    if escaped {
+                        self.store(0xFE);
+                    }
+                    self.escape = true;
+                }
+                byte => {
+                    if escaped {
+                        self.store(0xFE);
+                    }
+                    self.store(byte);
+                }
+            }
+        }
+        None
+    }
+
+    /// The next message in what came in, frames that do not decode are skipped.
+    pub fn receive(&mut self, now: u32) -> Option<Message> {
+        loop {
+            if let Ok(msg) = self.next(now)? {
+                return Some(msg);
+            }
+        }
+    }
+
+    fn store(&mut self, byte: u8) {
+        if self.frame.push(byte).is_err() {
+            self.overflow = true;
+        }
+    }
+
+    /// Decode the frame that just ended, `None` if it was a fragment of a message still coming.
+    fn end_frame(&mut self, now: u32) -> Option<Result<Message, Error>> {
+        let overflow = core::mem::replace(&mut self.overflow, false);
+        let result = match overflow {
+            true => Err(Error::Overflow),
+            false => {
+                postcard::from_bytes_cobs::<Message>(&mut self.frame).map_err(|_| Error::Frame)
+            }
+        };
+        self.frame.clear();
+        match result {
+            // fragments are held back until the message they belong to is complete.
+            Ok(Message {
+                id,
+                data: Data::Fragment(fragment),
+            }) => {
+                let bytes = self.reassembler.push(now, id, &fragment)?;
+                let data = postcard::from_bytes::<Data>(bytes)
+                    .ok()
+                    .filter(|data| !matches!(data, Data::Fragment(_)));
+                Some(
+                    data.ok_or(Error::Reassembled)
+                        .and_then(|data| self.open(Message { id, data })),
+                )
+            }
+            Ok(msg) => Some(self.open(msg)),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    // an authentic payload that does not decode is handed on still sealed, the caller decides what
+    // that means. One that fails the length or tag check never got past the seal.
+    fn open(&mut self, mut msg: Message) -> Result<Message, Error> {
+        let Some(key) = &self.session_key else {
+            return Ok(msg);
+        };
+        match &msg.data {
+            Data::Encrypted(sealed) => match sealed.open(key) {
+                Ok(opened) => msg.data = opened,
+                Err(envelope::Error::Deserialize) => {}
+                Err(_) => {
+                    self.unauthentic = self.unauthentic.saturating_add(1);
+                    return Err(Error::Unauthentic);
+                }
+            },
+            // anyone on the line can send in the clear, a command like that is not from the peer.
+            data if !data.is_link_setup() => return Err(Error::Plaintext),
+            _ => {}
+        }
+        Ok(msg)
+    }
+}
@@ -0,0 +1,240 @@
+use super::*;
+use crate::fragment::FRAGMENT_PAYLOAD;
+use crate::messages::{
+    Command, Encrypted, ErrorCode, ErrorReport, Status, Temperature, PROTOCOL_VERSION,
+};
+use crate::wire;
+
+const KEY: [u8; 8] = [0x5A; 8];
+
+fn encode(data: &Data) -> heapless::Vec<u8, 256> {
+    encode_any(data)
+}
+
+fn encode_any<T: serde::Serialize>(value: &T) -> heapless::Vec<u8, 256> {
+    postcard::to_vec(value).unwrap()
+}
+
+fn temperature(temp: f32) -> Data {
+    Data::Temperature(Temperature { temp })
+}
+
+/// The sample that takes the most bytes, sealed so it needs more than one fragment.
+fn largest() -> Data {
+    let data = crate::envelope::tests::samples()
+        .into_iter()
+        .max_by_key(|data| encode(data).len())
+        .unwrap();
+    let sealed = Data::Encrypted(Encrypted::seal(&KEY, u32::MAX, &data).unwrap());
+    assert!(encode(&sealed).len() > FRAGMENT_PAYLOAD);
+    sealed
+}
+
+fn put(receiver: &mut Receiver, id: u8, data: &Data) {
+    wire::write(id, data, PROTOCOL_VERSION, |byte| receiver.push(byte)).unwrap();
+}
+
+fn drain(receiver: &mut Receiver) -> std::vec::Vec<Result<Message, Error>> {
+    core::iter::from_fn(|| receiver.next(0)).collect()
+}
+
+fn assert_message(result: &Result<Message, Error>, id: u8, data: &Data) {
+    let msg = result.as_ref().unwrap();
+    assert_eq!(msg.id, id);
+    assert_eq!(encode(&msg.data), encode(data));
+}
+
+#[test]
+fn back_to_back_frames_each_come_out() {
+    let mut receiver = Receiver::new();
+    let sent = [temperature(1.0), temperature(f32::NAN), temperature(-2.5)];
+    for (id, data) in sent.iter().enumerate() {
+        put(&mut receiver, id as u8, data);
+    }
+    assert!(receiver.has_new_message());
+    let received = drain(&mut receiver);
+    assert_eq!(received.len(), sent.len());
+    for (id, (result, data)) in received.iter().zip(&sent).enumerate() {
+        assert_message(result, id as u8, data);
+    }
+    assert!(!receiver.has_new_message());
+}
+
+#[test]
+fn escaped_bytes_are_unstuffed() {
+    // every 0xFF in the key is sent escaped, and the 0xFE next to them is data.
+    let mut receiver = Receiver::new();
+    let data = Data::AESKey([0xFF, 0xFE, 0xFF, 0xFE, 0xFE, 0xFF, 0x00, 0xFF]);
+    put(&mut receiver, 0xFF, &data);
+    let received = drain(&mut receiver);
+    assert_eq!(received.len(), 1);
+    assert_message(&received[0], 0xFF, &data);
+}
+
+#[test]
+fn only_a_whole_frame_is_a_new_message() {
+    let mut receiver = Receiver::new();
+    let mut bytes = std::vec::Vec::new();
+    wire::write(3, &temperature(f32::NAN), PROTOCOL_VERSION, |byte| {
+        bytes.push(byte)
+    })
+    .unwrap();
+    let (last, rest) = bytes.split_last().unwrap();
+    for &byte in rest {
+        receiver.push(byte);
+        assert!(!receiver.has_new_message());
+    }
+    assert!(receiver.next(0).is_none());
+    receiver.push(*last);
+    assert!(receiver.has_new_message());
+    assert_message(&receiver.next(0).unwrap(), 3, &temperature(f32::NAN));
+}
+
+#[test]
+fn fragments_are_reassembled() {
+    let data = largest();
+    let mut receiver = Receiver::new();
+    wire::write_fragments(9, &data, PROTOCOL_VERSION, |byte| receiver.push(byte)).unwrap();
+    put(&mut receiver, 10, &temperature(3.0));
+    let received = drain(&mut receiver);
+    assert_eq!(received.len(), 2);
+    assert_message(&received[0], 9, &data);
+    assert_message(&received[1], 10, &temperature(3.0));
+}
+
+#[test]
+fn a_bad_frame_does_not_take_the_next_one_with_it() {
+    let mut receiver = Receiver::new();
+    for byte in [0x01, 0x02, 0x03, 0xFF] {
+        receiver.push(byte);
+    }
+    for byte in [0x42; FRAME_SIZE + 1] {
+        receiver.push(byte);
+    }
+    receiver.push(0xFF);
+    put(&mut receiver, 1, &temperature(4.0));
+    let received = drain(&mut receiver);
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[0].as_ref().err(), Some(&Error::Frame));
+    assert_eq!(received[1].as_ref().err(), Some(&Error::Overflow));
+    assert_message(&received[2], 1, &temperature(4.0));
+}
+
+#[test]
+fn receive_skips_frames_that_do_not_decode() {
+    let mut receiver = Receiver::new();
+    for byte in [0x01, 0x02, 0xFF] {
+        receiver.push(byte);
+    }
+    put(&mut receiver, 2, &temperature(5.0));
+    let msg = receiver.receive(0).unwrap();
+    assert_eq!(encode(&msg.data), encode(&temperature(5.0)));
+    assert!(receiver.receive(0).is_none());
+}
+
+#[test]
+fn sealed_messages_are_opened_with_the_session_key() {
+    let command = Data::Command(Command::ReportWear);
+    let sealed = Data::Encrypted(Encrypted::seal(&KEY, 1, &command).unwrap());
+    let mut receiver = Receiver::new();
+    receiver.set_session_key(Some(&KEY));
+    put(&mut receiver, 1, &sealed);
+    assert_message(&receiver.next(0).unwrap(), 1, &command);
+    assert_eq!(receiver.unauthentic(), 0);
+}
+
+/// Anyone can send something sealed under another key, it must not get past the receiver.
+#[test]
+fn a_seal_that_does_not_check_out_is_dropped_and_counted() {
+    let command = Data::Command(Command::WipeAllKeys);
+    let mut receiver = Receiver::new();
+    receiver.set_session_key(Some(&KEY));
+    for id in 1..=3 {
+        let sealed = Encrypted::seal(&[0; 8], id, &command).unwrap();
+        put(&mut receiver, id as u8, &Data::Encrypted(sealed));
+    }
+    for _ in 1..=3 {
+        assert_eq!(receiver.next(0).unwrap().err(), Some(Error::Unauthentic));
+    }
+    assert_eq!(receiver.unauthentic(), 3);
+}
+
+/// Only the peer holds the key, so a seal that checks out but does not decode is handed on for the
+/// node to rekey.
+#[test]
+fn an_authentic_seal_that_does_not_decode_is_handed_on() {
+    // no `Data` variant has this index.
+    let garbage = Encrypted::<u8>::seal(&KEY, 1, &0xF0).unwrap();
+    let sealed = Data::Encrypted(postcard::from_bytes(&encode_any(&garbage)).unwrap());
+    let mut receiver = Receiver::new();
+    receiver.set_session_key(Some(&KEY));
+    put(&mut receiver, 4, &sealed);
+    assert_message(&receiver.next(0).unwrap(), 4, &sealed);
+    assert_eq!(receiver.unauthentic(), 0);
+}
+
+#[test]
+fn a_full_queue_is_handed_over_before_it_drops_bytes() {
+    let mut receiver = Receiver::new();
+    let mut pushed = 0;
+    while !receiver.has_new_message() {
+        receiver.push(0x42);
+        pushed += 1;
+    }
+    assert_eq!(pushed, 256);
+    assert!(receiver.next(0).is_none());
+    receiver.push(0xFF);
+    put(&mut receiver, 1, &temperature(6.0));
+    let received = drain(&mut receiver);
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].as_ref().err(), Some(&Error::Overflow));
+    assert_message(&received[1], 1, &temperature(6.0));
+}
+
+#[test]
+fn commands_in_the_clear_are_turned_down_once_there_is_a_session_key() {
+    let wipe = Data::Command(Command::WipeAllKeys);
+    let mut receiver = Receiver::new();
+    put(&mut receiver, 1, &wipe);
+    assert_message(&receiver.next(0).unwrap(), 1, &wipe);
+
+    receiver.set_session_key(Some(&KEY));
+    put(&mut receiver, 2, &wipe);
+    wire::write_fragments(3, &wipe, PROTOCOL_VERSION, |byte| receiver.push(byte)).unwrap();
+    assert_eq!(receiver.next(0).unwrap().err(), Some(Error::Plaintext));
+    assert_eq!(receiver.next(0).unwrap().err(), Some(Error::Plaintext));
+    assert!(receiver.receive(0).is_none());
+
+    let sealed = Data::Encrypted(Encrypted::seal(&KEY, 1, &wipe).unwrap());
+    put(&mut receiver, 4, &sealed);
+    assert_message(&receiver.next(0).unwrap(), 4, &wipe);
+}
+
+#[test]
+fn the_link_setup_still_comes_in_the_clear() {
+    let mut receiver = Receiver::new();
+    receiver.set_session_key(Some(&KEY));
+    let setup = [Data::AESKey([1; 8]), Data::Status(Status::UnkownAESKey)];
+    for (id, data) in setup.iter().enumerate() {
+        put(&mut receiver, id as u8, data);
+        assert_message(&receiver.next(0).unwrap(), id as u8, data);
+    }
+}
+
+/// A report like that would make the node drop its keys, so it has to come sealed.
+#[test]
+fn failure_reports_in_the_clear_are_turned_down_once_there_is_a_session_key() {
+    let mut receiver = Receiver::new();
+    receiver.set_session_key(Some(&KEY));
+    for (id, code) in [ErrorCode::DecryptFailed, ErrorCode::AuthFailed]
+        .into_iter()
+        .enumerate()
+    {
+        let report = Data::Status(Status::Error(ErrorReport {
+            code,
+            message_id: 7,
+        }));
+        put(&mut receiver, id as u8, &report);
+        assert_eq!(receiver.next(0).unwrap().err(), Some(Error::Plaintext));
+    }
+}
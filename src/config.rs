@@ -0,0 +1,97 @@
+//! Persistent node configuration
+//!
+//! The config lives in its own block in the gap between the calibration block and the identity
+//! slot. A blank, torn or outdated block reads back as the defaults, so a fresh board boots with
+//! the values that used to be compiled in. The AT25010B has no room for the block, a board with one
+//! always runs on the defaults.
+
+use crate::eeprom::block::{self, Block};
+use crate::eeprom::{Device, Storage};
+use crate::filter::Sampling;
+use crate::key_store;
+use crate::temperature::CALIBRATION_END;
+
+#[cfg(test)]
+mod tests;
+
+/// Bump whenever `Config` changes shape, older records then fall back to the defaults.
+pub const CONFIG_VERSION: u8 = 3;
+
+const BLOCK: Block = Block::new(CALIBRATION_END as u16, 24, CONFIG_VERSION);
+
+/// Longest postcard encoding of a `Config`: a varint `u32` takes up to 5 bytes and an `i16` up to 3,
+/// `Role::Sensor` is a variant index and a `u16`, and `Sampling` is 4 bytes.
+pub const MAX_ENCODED_LEN: usize = 5 + 5 + 3 + (1 + 3) + 4;
+
+pub const START: usize = BLOCK.address as usize;
+
+/// End of the config block, the wear counts follow, see `wear`.
+pub const END: usize = BLOCK.end();
+
+// The config block must hold any config. It does not fit on the AT25010B, every larger part has
+// room for it below the identity slot, the AT25020B the least.
+const _: () = {
+    assert!(BLOCK.max_payload_len() >= MAX_ENCODED_LEN);
+    assert!(END <= key_store::free_end(Device::AT25020B.capacity));
+};
+
+/// What the node does with temperatures.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    /// Samples the sensor and sends a reading to the peer every `period_ms`.
+    Sensor { period_ms: u16 },
+    /// Receives readings and drives the LEDs.
+    Monitor,
+}
+
+/// Baud rate and PWM frequency are applied at boot, the rest takes effect right away. The encoding
+/// has to fit the payload of the config block, hence the integer threshold.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub baud_rate: u32,
+    pub pwm_khz: u32,
+    /// Above this temperature, in tenths of a °C, the red LED is lit.
+    pub temp_threshold: i16,
+    pub role: Role,
+    /// How the local temperature sensor is sampled.
+    pub sampling: Sampling,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            baud_rate: 115_200,
+            pwm_khz: 20,
+            temp_threshold: 1000,
+            role: Role::Monitor,
+            sampling: Sampling::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn threshold_celsius(&self) -> f32 {
+        self.temp_threshold as f32 / 10.0
+    }
+
+    /// Read the config from the chip, or the defaults if there is no valid record.
+    pub fn load<S: Storage>(storage: &mut S) -> Config {
+        if !fits(storage) {
+            return Config::default();
+        }
+        BLOCK.load(storage).unwrap_or_default()
+    }
+
+    /// Write the config to the chip, skipped when the stored record is already identical.
+    pub fn store<S: Storage>(&self, storage: &mut S) -> Result<(), block::Error> {
+        if !fits(storage) {
+            return Err(block::Error::NoRoom);
+        }
+        BLOCK.store(storage, self)
+    }
+}
+
+/// Whether the part has room for the config block below the identity slot.
+fn fits<S: Storage>(storage: &S) -> bool {
+    END <= key_store::free_end(storage.device().capacity)
+}
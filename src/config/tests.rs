@@ -0,0 +1,49 @@
+use super::*;
+use crate::eeprom::sim::SimEeprom;
+use crate::eeprom::Device;
+use crate::filter::Filter;
+
+fn largest() -> Config {
+    Config {
+        baud_rate: u32::MAX,
+        pwm_khz: u32::MAX,
+        temp_threshold: i16::MIN,
+        role: Role::Sensor {
+            period_ms: u16::MAX,
+        },
+        sampling: Sampling {
+            oversample: u8::MAX,
+            reject_outliers: true,
+            filter: Filter::Median(u8::MAX),
+        },
+    }
+}
+
+#[test]
+fn the_largest_config_is_no_longer_than_counted() {
+    let mut buf = [0u8; 64];
+    let encoded = postcard::to_slice(&largest(), &mut buf).unwrap();
+    assert_eq!(encoded.len(), MAX_ENCODED_LEN);
+}
+
+#[test]
+fn the_largest_config_reads_back_after_a_reboot() {
+    let mut sim = SimEeprom::new(Device::AT25020B);
+    largest().store(&mut sim).unwrap();
+    assert_eq!(Config::load(&mut sim), largest());
+}
+
+#[test]
+fn a_blank_block_reads_as_the_defaults() {
+    let mut sim = SimEeprom::new(Device::AT25020B);
+    assert_eq!(Config::load(&mut sim), Config::default());
+}
+
+/// The AT25010B has no room for the block, the node runs on the defaults and the store is refused.
+#[test]
+fn the_smallest_part_has_no_config_block() {
+    let mut sim = SimEeprom::new(Device::AT25010B);
+    assert_eq!(largest().store(&mut sim), Err(block::Error::NoRoom));
+    assert_eq!(sim.bytes_written(), 0);
+    assert_eq!(Config::load(&mut sim), Config::default());
+}
@@ -1,9 +1,13 @@
 //! RSA no std implementation
 //! INSECURE: This is a toy implementation and should not be used in production code.
 
-use heapless::Vec;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{compiler_fence, Ordering};
 
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[cfg(test)]
+mod tests;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct RSAPublicKey {
     n: u64,
     e: u64,
@@ -15,6 +19,14 @@ pub struct RSAPrivateKey {
     d: u64,
 }
 
+/// Both halves of a key pair. The modulus is shared so it is only stored once.
+#[derive(Clone)]
+pub struct RSAKeyPair {
+    n: u64,
+    e: u64,
+    d: u64,
+}
+
 impl RSAPublicKey {
     pub fn new(n: u64, e: u64) -> RSAPublicKey {
         RSAPublicKey { n, e }
@@ -38,18 +50,107 @@ impl RSAPrivateKey {
     pub fn new(n: u64, d: u64) -> RSAPrivateKey {
         RSAPrivateKey { n, d }
     }
-    pub fn to_bytes(&self) -> [u8; 16] {
-        let mut bytes = [0u8; 16];
-        for i in 0..8 {
-            bytes[i] = (self.n >> (i * 8)) as u8;
-            bytes[i + 8] = (self.d >> (i * 8)) as u8;
-        }
+}
+
+/// The pair every board is provisioned with. The modulus is the product of the two largest 32 bit
+/// primes, so anyone can factor it, but it is large enough for any message below 2^63.
+pub const PROVISIONED: RSAKeyPair =
+    RSAKeyPair::new(0xFFFF_FFEA_0000_0055, 0x10001, 0x8181_7E72_5D5D_A2D9);
+
+impl RSAKeyPair {
+    pub const fn new(n: u64, e: u64, d: u64) -> RSAKeyPair {
+        RSAKeyPair { n, e, d }
+    }
+    pub fn public(&self) -> RSAPublicKey {
+        RSAPublicKey::new(self.n, self.e)
+    }
+    pub fn private(&self) -> RSAPrivateKey {
+        RSAPrivateKey::new(self.n, self.d)
+    }
+    pub fn to_bytes(&self) -> [u8; 24] {
+        let mut bytes = [0u8; 24];
+        bytes[0..8].copy_from_slice(&self.n.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.e.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.d.to_le_bytes());
         bytes
     }
-    pub fn from_bytes(bytes: &[u8; 16]) -> RSAPrivateKey {
+    pub fn from_bytes(bytes: &[u8; 24]) -> RSAKeyPair {
         let n = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
-        let d = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
-        RSAPrivateKey { n, d }
+        let e = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        let d = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+        RSAKeyPair { n, e, d }
+    }
+}
+
+/// Overwrite a value in place with zeros.
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+impl<const N: usize> Zeroize for [u8; N] {
+    fn zeroize(&mut self) {
+        for byte in self.iter_mut() {
+            // volatile so the compiler cannot drop the store as dead before the memory is freed.
+            unsafe { core::ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Zeroize for u64 {
+    fn zeroize(&mut self) {
+        unsafe { core::ptr::write_volatile(self, 0) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Zeroize for RSAPublicKey {
+    fn zeroize(&mut self) {
+        self.n.zeroize();
+        self.e.zeroize();
+    }
+}
+
+impl Zeroize for RSAPrivateKey {
+    fn zeroize(&mut self) {
+        self.n.zeroize();
+        self.d.zeroize();
+    }
+}
+
+impl Zeroize for RSAKeyPair {
+    fn zeroize(&mut self) {
+        self.n.zeroize();
+        self.e.zeroize();
+        self.d.zeroize();
+    }
+}
+
+/// Key material that is wiped from RAM when it goes out of scope.
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Secret<T> {
+        Secret(value)
+    }
+}
+
+impl<T: Zeroize> Deref for Secret<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Secret<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
     }
 }
 
@@ -65,20 +166,22 @@ pub fn decrypt(priv_key: &RSAPrivateKey, c: &[u8]) -> [u8; 8] {
     u64_to_bytes(decrypted_int)
 }
 
-fn mod_exp(mut base: u64, mut exp: u64, modulus: u64) -> u64 {
+fn mod_exp(base: u64, mut exp: u64, modulus: u64) -> u64 {
     if modulus == 1 {
         return 0;
     }
+    // the products of two residues need twice the width of the modulus.
+    let modulus = modulus as u128;
+    let mut base = base as u128 % modulus;
     let mut result = 1;
-    base = base % modulus;
     while exp > 0 {
         if exp % 2 == 1 {
-            result = (result * base) % modulus;
+            result = result * base % modulus;
         }
-        exp = exp >> 1;
-        base = (base * base) % modulus;
+        exp >>= 1;
+        base = base * base % modulus;
     }
-    result
+    result as u64
 }
 
 fn bytes_to_u64(bytes: &[u8]) -> u64 {
@@ -100,7 +203,7 @@ fn u64_to_bytes(mut value: u64) -> [u8; 8] {
 }
 
 // AES Encryption and Decryption
-const AES_BLOCK_SIZE: usize = 8;
+pub const AES_BLOCK_SIZE: usize = 8;
 
 fn aes_encrypt_block(key: &[u8; 8], block: &mut [u8; 8]) {
     // Implement AES-128 encryption for a single block
@@ -118,45 +221,37 @@ fn aes_decrypt_block(key: &[u8; 8], block: &mut [u8; 8]) {
     }
 }
 
-pub fn aes_encrypt(key: &[u8; 8], data: &[u8]) -> Vec<u8, 256> {
-    let mut encrypted_data: Vec<u8, 256> = Vec::new();
-    let mut iv = [0u8; AES_BLOCK_SIZE]; // Deterministic IV for simplicity
-
-    for chunk in data.chunks(AES_BLOCK_SIZE) {
-        let mut block = [0u8; AES_BLOCK_SIZE];
-        block[..chunk.len()].copy_from_slice(chunk);
-        for i in 0..AES_BLOCK_SIZE {
-            block[i] ^= iv[i];
+/// Encrypt `data` in place, CBC with the given IV. `data` must be a whole number of blocks.
+pub fn aes_wrap(key: &[u8; 8], iv: &[u8; 8], data: &mut [u8]) {
+    let mut iv = *iv;
+    for chunk in data.as_chunks_mut::<AES_BLOCK_SIZE>().0 {
+        let mut block = Secret::new(*chunk);
+        for (byte, iv) in block.iter_mut().zip(iv) {
+            *byte ^= iv;
         }
         aes_encrypt_block(key, &mut block);
-        iv = block;
-        encrypted_data.extend_from_slice(&block).unwrap();
+        *chunk = *block;
+        iv = *block;
     }
-
-    encrypted_data
 }
 
-pub fn aes_decrypt(key: &[u8; 8], encrypted_data: &[u8]) -> Vec<u8, 256> {
-    let mut decrypted_data: Vec<u8, 256> = Vec::new();
-    let mut iv = [0u8; AES_BLOCK_SIZE]; // Deterministic IV for simplicity
-
-    for chunk in encrypted_data.chunks(AES_BLOCK_SIZE) {
-        let mut block = [0u8; AES_BLOCK_SIZE];
-        block.copy_from_slice(chunk);
-        let mut decrypted_block = block;
-        aes_decrypt_block(key, &mut decrypted_block);
-        for i in 0..AES_BLOCK_SIZE {
-            decrypted_block[i] ^= iv[i];
+/// Reverse of `aes_wrap`.
+pub fn aes_unwrap(key: &[u8; 8], iv: &[u8; 8], data: &mut [u8]) {
+    let mut iv = *iv;
+    for chunk in data.as_chunks_mut::<AES_BLOCK_SIZE>().0 {
+        let mut block = Secret::new(*chunk);
+        let next_iv = *block;
+        aes_decrypt_block(key, &mut block);
+        for (byte, iv) in block.iter_mut().zip(iv) {
+            *byte ^= iv;
         }
-        iv = block;
-        decrypted_data.extend_from_slice(&decrypted_block).unwrap();
+        *chunk = *block;
+        iv = next_iv;
     }
-
-    decrypted_data
 }
 
 // FNV-1a Hash Function
-fn fnv1a_hash(data: &[u8]) -> [u8; 8] {
+fn fnv1a_hash<'a>(data: impl IntoIterator<Item = &'a u8>) -> [u8; 8] {
     const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
     const FNV_PRIME: u64 = 0x100000001b3;
 
@@ -166,14 +261,28 @@ fn fnv1a_hash(data: &[u8]) -> [u8; 8] {
         hash = hash.wrapping_mul(FNV_PRIME);
     }
 
-    let mut key = [0u8; 8];
-    for i in 0..8 {
-        key[i] = (hash >> (i * 8)) as u8;
-    }
-    key
+    hash.to_le_bytes()
 }
 
 // Generate AES Key
 pub fn generate_aes_key(seed: &[u8]) -> [u8; 8] {
-    fnv1a_hash(seed)
+    let mut key = fnv1a_hash(seed);
+    // keep the key below any modulus of 2^63 and up, so it survives being sent with RSA.
+    key[0] &= 0x7F;
+    key
+}
+
+/// Key-encryption key for data at rest, derived from the 96 bit unique ID of the MCU so that it
+/// differs from board to board and never has to be stored.
+pub fn derive_kek(device_id: &[u8; 12]) -> Secret<[u8; 8]> {
+    let mut input = Secret::new([0u8; 16]);
+    input[..4].copy_from_slice(b"KEK0");
+    input[4..].copy_from_slice(device_id);
+    Secret::new(fnv1a_hash(&input[..]))
+}
+
+/// Authentication tag over `data`, FNV-1a of the data with the key on both ends. Unlike a CBC-MAC it
+/// stays keyed with the XOR block cipher above, where the key cancels out of an even chain.
+pub fn mac(key: &[u8; 8], data: &[u8]) -> [u8; 8] {
+    fnv1a_hash(b"MAC0".iter().chain(key).chain(data).chain(key))
 }
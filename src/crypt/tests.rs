@@ -0,0 +1,58 @@
+use super::*;
+
+#[test]
+fn session_keys_survive_the_provisioned_pair() {
+    let public = PROVISIONED.public();
+    let private = PROVISIONED.private();
+    let keys = [
+        [0; 8],
+        [0, 0, 0, 0, 0, 0, 0, 1],
+        [0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        generate_aes_key(&[0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8]),
+        generate_aes_key(b"another seed"),
+    ];
+    for key in keys {
+        let sealed = encrypt(&public, &key);
+        assert_eq!(decrypt(&private, &sealed), key, "{key:02x?}");
+    }
+    // textbook RSA leaves 0 and 1 as they are, real keys do change.
+    let key = keys[3];
+    assert_ne!(encrypt(&public, &key), key);
+}
+
+#[test]
+fn generated_keys_stay_below_the_modulus() {
+    for seed in 0u8..=255 {
+        let key = generate_aes_key(&[seed]);
+        assert!(u64::from_be_bytes(key) < PROVISIONED.n, "seed {seed}");
+    }
+}
+
+#[test]
+fn keys_read_back_from_their_bytes() {
+    let public = PROVISIONED.public();
+    assert_eq!(RSAPublicKey::from_bytes(&public.to_bytes()), public);
+    let pair = RSAKeyPair::from_bytes(&PROVISIONED.to_bytes());
+    assert_eq!(pair.public(), public);
+    assert_eq!(pair.d, PROVISIONED.d);
+}
+
+#[test]
+fn aes_unwrap_reverses_aes_wrap() {
+    let key = [0x3a, 0x91, 0x07, 0xc4, 0x5e, 0x22, 0xf0, 0x68];
+    let iv = [0x10; 8];
+    let plain: [u8; 24] = core::array::from_fn(|i| i as u8);
+    let mut data = plain;
+    aes_wrap(&key, &iv, &mut data);
+    assert_ne!(data, plain);
+    aes_unwrap(&key, &iv, &mut data);
+    assert_eq!(data, plain);
+}
+
+#[test]
+fn the_kek_differs_between_boards() {
+    let one = derive_kek(b"test-board-1");
+    let two = derive_kek(b"test-board-2");
+    assert_ne!(*one, *two);
+    assert_eq!(*one, *derive_kek(b"test-board-1"));
+}
@@ -1,15 +1,29 @@
-//! EEPROM driver for AT25010B
+//! EEPROM driver for the AT25xxx family
 //!
+//! The parts only differ in size, page size and how the address is sent, so one driver covers
+//! them all. The geometry is picked at construction from the `Device` constants in
+//! `storage`.
 
 use stm32f0xx_hal::pac;
 use stm32f0xx_hal::prelude::*;
 use stm32f0xx_hal::spi::{Mode, Phase, Polarity, Spi};
 
-pub const AES_KEY_ADDRESS: u8 = 0x00;
-pub const RSA_PUB_KEY_ADDRESS: u8 = 0x08; // AES key is 8 bytes, so the next address starts at 0x08
-pub const RSA_PRIV_KEY_ADDRESS: u8 = 0x18; // RSA public key is 16 bytes, so the next address starts at 0x18
-pub const FORIEGN_AES_KEY_ADDRESS: u8 = 0x28; // RSA private key is 16 bytes, so the next address starts at 0x28
-pub const FORIEGN_RSA_PUB_KEY_ADDRESS: u8 = 0x30;
+pub mod block;
+mod storage;
+
+pub use storage::*;
+
+impl Device {
+    /// Opcode and address bytes for a READ or WRITE at `address`, and how many of them to send.
+    fn command(&self, opcode: u8, address: u16) -> ([u8; 3], usize) {
+        let [high, low] = address.to_be_bytes();
+        match self.address_bytes {
+            1 if self.a8_in_opcode => ([opcode | (high & 1) << 3, low, 0], 2),
+            1 => ([opcode, low, 0], 2),
+            _ => ([opcode, high, low], 3),
+        }
+    }
+}
 
 pub struct EepromManager {
     spi: Spi<
@@ -20,6 +34,7 @@ pub struct EepromManager {
         stm32f0xx_hal::spi::EightBit,
     >,
     status: Option<StatusRegister>, // Cache the status register, on boot we do not know the status, so we wrap in an option.
+    device: Device,
 }
 
 #[derive(Debug, Clone)]
@@ -33,19 +48,39 @@ pub struct StatusRegister {
 impl StatusRegister {
     pub fn from_byte(byte: u8) -> Self {
         StatusRegister {
-            bp1: (byte & 0b0000_0100) != 0,
-            bp0: (byte & 0b0000_0010) != 0,
-            wel: (byte & 0b0000_0001) != 0,
+            bp1: (byte & 0b0000_1000) != 0,
+            bp0: (byte & 0b0000_0100) != 0,
+            wel: (byte & 0b0000_0010) != 0,
             rdy_bsy: (byte & 0b0000_0001) != 0,
         }
     }
 
     pub fn to_byte(&self) -> u8 {
-        (if self.bp1 { 0b0000_0100 } else { 0 })
-            | (if self.bp0 { 0b0000_0010 } else { 0 })
-            | (if self.wel { 0b0000_0001 } else { 0 })
+        (if self.bp1 { 0b0000_1000 } else { 0 })
+            | (if self.bp0 { 0b0000_0100 } else { 0 })
+            | (if self.wel { 0b0000_0010 } else { 0 })
             | (if self.rdy_bsy { 0b0000_0001 } else { 0 })
     }
+
+    pub fn block_protect(&self) -> BlockProtect {
+        match (self.bp1, self.bp0) {
+            (false, false) => BlockProtect::None,
+            (false, true) => BlockProtect::UpperQuarter,
+            (true, false) => BlockProtect::UpperHalf,
+            (true, true) => BlockProtect::All,
+        }
+    }
+
+    pub fn set_block_protect(&mut self, protect: BlockProtect) {
+        let (bp1, bp0) = match protect {
+            BlockProtect::None => (false, false),
+            BlockProtect::UpperQuarter => (false, true),
+            BlockProtect::UpperHalf => (true, false),
+            BlockProtect::All => (true, true),
+        };
+        self.bp1 = bp1;
+        self.bp0 = bp0;
+    }
 }
 
 impl EepromManager {
@@ -68,6 +103,7 @@ impl EepromManager {
         >,
         rcc: &mut stm32f0xx_hal::rcc::Rcc,
         spi_peripheral: pac::SPI1,
+        device: Device,
     ) -> EepromManager {
         let spi = Spi::spi1(
             spi_peripheral,
@@ -79,7 +115,14 @@ impl EepromManager {
             1.mhz(),
             rcc,
         );
-        EepromManager { spi, status: None }
+        let mut eeprom_manager = EepromManager {
+            spi,
+            status: None,
+            device,
+        };
+        // populate the status cache so we know which ranges are protected before the first write.
+        eeprom_manager.read_status();
+        eeprom_manager
     }
 
     pub fn write_enable(&mut self) {
@@ -100,21 +143,48 @@ impl EepromManager {
     }
 
     pub fn write_status(&mut self, status: StatusRegister) {
+        self.write_enable();
         self.spi.write(&[Self::WRSR, status.to_byte()]).unwrap();
+        self.wait_until_ready();
     }
 
-    pub fn read_memory(&mut self, address: u8) -> u8 {
-        let mut data = [0];
-        self.spi.write(&[Self::READ, address]).unwrap();
-        self.spi.transfer(&mut data).unwrap();
-        data[0]
+    pub fn block_protect(&mut self) -> BlockProtect {
+        match self.status {
+            Some(ref status) => status.block_protect(),
+            None => self.read_status().block_protect(),
+        }
+    }
+
+    pub fn set_block_protect(&mut self, protect: BlockProtect) {
+        let mut status = self.read_status();
+        status.set_block_protect(protect);
+        self.write_status(status);
+        // read back so the cache reflects what the chip actually latched.
+        self.read_status();
     }
 
-    pub fn write_memory(&mut self, address: u8, data: u8) {
+    /// Sequential read, the chip keeps incrementing the address for as long as we clock.
+    pub fn read_memory(&mut self, address: u16, buf: &mut [u8]) {
+        let (command, len) = self.device.command(Self::READ, address);
+        self.spi.write(&command[..len]).unwrap();
+        self.spi.transfer(buf).unwrap();
+    }
+
+    /// Program up to a page in one write cycle. `data` must not cross a page boundary, the chip
+    /// would wrap around to the start of the page.
+    pub fn write_page(&mut self, address: u16, data: &[u8]) -> Result<(), Error> {
+        // refuse the write here, the chip would silently ignore it.
+        let last = address + data.len() as u16 - 1;
+        if self.block_protect().protects(self.device.capacity, last) {
+            return Err(Error::WriteProtected(last));
+        }
+        let (command, len) = self.device.command(Self::WRITE, address);
         self.write_enable();
-        self.spi.write(&[Self::WRITE, address, data]).unwrap();
+        self.spi.write(&command[..len]).unwrap();
+        self.spi.write(data).unwrap();
         self.wait_until_ready();
         self.write_disable();
+        Ok(())
     }
 
     pub fn wait_until_ready(&mut self) {
@@ -125,32 +195,34 @@ impl EepromManager {
             }
         }
     }
+}
 
-    pub fn read_8_byte_key(&mut self, address: u8) -> [u8; 8] {
-        let mut key = [0; 8];
-        for i in 0..8 {
-            key[i] = self.read_memory(address + i as u8);
-        }
-        key
+impl Storage for EepromManager {
+    fn device(&self) -> Device {
+        self.device
     }
 
-    pub fn write_8_byte_key(&mut self, key: [u8; 8], address: u8) {
-        for i in 0..8 {
-            self.write_memory(address + i, key[i as usize]);
-        }
+    fn read_bytes(&mut self, address: u16, buf: &mut [u8]) {
+        self.read_memory(address, buf);
     }
 
-    pub fn read_16_byte_key(&mut self, address: u8) -> [u8; 16] {
-        let mut key = [0; 16];
-        for i in 0..16 {
-            key[i] = self.read_memory(address + i as u8);
+    fn write_bytes(&mut self, mut address: u16, mut data: &[u8]) -> Result<(), Error> {
+        // split at page boundaries, each piece is one write cycle.
+        while !data.is_empty() {
+            let room = self.device.page_size - address as usize % self.device.page_size;
+            let (page, rest) = data.split_at(room.min(data.len()));
+            self.write_page(address, page)?;
+            address += page.len() as u16;
+            data = rest;
         }
-        key
+        Ok(())
     }
 
-    pub fn write_16_byte_key(&mut self, key: [u8; 16], address: u8) {
-        for i in 0..16 {
-            self.write_memory(address + i, key[i as usize]);
-        }
+    fn block_protect(&mut self) -> BlockProtect {
+        EepromManager::block_protect(self)
+    }
+
+    fn set_block_protect(&mut self, protect: BlockProtect) {
+        EepromManager::set_block_protect(self, protect)
     }
 }
@@ -0,0 +1,99 @@
+//! Small settings records
+//!
+//! A block is a fixed region holding one postcard encoded value as `[version, len, payload.., crc]`.
+//! A blank, torn or outdated block reads back as `None` and the owner falls back to its defaults.
+//! Blocks are not double-buffered, a power cut during an update only costs the settings.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{crc8, Storage};
+
+const HEADER_LEN: usize = 2; // version + len
+const CRC_LEN: usize = 1;
+
+/// Largest block we support, used to size scratch buffers.
+pub const MAX_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Eeprom(super::Error),
+    /// The encoded value does not fit in the block.
+    TooLarge,
+    /// The part has no room for the block.
+    NoRoom,
+}
+
+impl From<super::Error> for Error {
+    fn from(err: super::Error) -> Self {
+        Error::Eeprom(err)
+    }
+}
+
+pub struct Block {
+    pub address: u16,
+    pub len: usize,
+    /// Bump whenever the stored type changes shape, older blocks then read back as `None`.
+    pub version: u8,
+}
+
+impl Block {
+    pub const fn new(address: u16, len: usize, version: u8) -> Block {
+        assert!(len <= MAX_LEN && len > HEADER_LEN + CRC_LEN);
+        Block {
+            address,
+            len,
+            version,
+        }
+    }
+
+    pub const fn end(&self) -> usize {
+        self.address as usize + self.len
+    }
+
+    pub fn load<T: DeserializeOwned, S: Storage>(&self, storage: &mut S) -> Option<T> {
+        let mut record = [0u8; MAX_LEN];
+        let record = &mut record[..self.len];
+        storage.read_bytes(self.address, record);
+
+        let len = record[1] as usize;
+        if record[0] != self.version || len > self.max_payload_len() {
+            return None;
+        }
+        let body_len = HEADER_LEN + len;
+        if record[body_len] != crc8(&record[..body_len]) {
+            return None;
+        }
+        postcard::from_bytes(&record[HEADER_LEN..body_len]).ok()
+    }
+
+    /// Write `value`, skipped when the stored record is already identical.
+    pub fn store<T: Serialize, S: Storage>(&self, storage: &mut S, value: &T) -> Result<(), Error> {
+        let mut record = [0u8; MAX_LEN];
+        let len = postcard::to_slice(
+            value,
+            &mut record[HEADER_LEN..HEADER_LEN + self.max_payload_len()],
+        )
+        .map_err(|_| Error::TooLarge)?
+        .len();
+        record[0] = self.version;
+        record[1] = len as u8;
+        let body_len = HEADER_LEN + len;
+        record[body_len] = crc8(&record[..body_len]);
+        let record = &record[..body_len + CRC_LEN];
+
+        let mut stored = [0u8; MAX_LEN];
+        let stored = &mut stored[..record.len()];
+        storage.read_bytes(self.address, stored);
+        if stored == record {
+            return Ok(());
+        }
+        storage.write_bytes(self.address, record)?;
+        Ok(())
+    }
+
+    /// Largest encoded value the block holds.
+    pub const fn max_payload_len(&self) -> usize {
+        self.len - HEADER_LEN - CRC_LEN
+    }
+}
@@ -0,0 +1,103 @@
+//! In-memory model of an AT25xxx part
+//!
+//! Lets the key store run off target. A power cut can be scheduled after any number of written
+//! bytes: everything from that point on is dropped, exactly like a board that browned out in the
+//! middle of a write. Restoring power and building a new key store on top of the same simulator is
+//! the equivalent of a reboot.
+//!
+//! The simulator takes any `Device`, the backing array is sized for the largest part and only the
+//! first `capacity` bytes are in use. Only the host tests build it.
+
+use super::{BlockProtect, Device, Error, Storage};
+
+/// Size of the AT25256B, the largest part we support.
+const MAX_CAPACITY: usize = Device::AT25256B.capacity;
+
+pub struct SimEeprom {
+    device: Device,
+    memory: [u8; MAX_CAPACITY],
+    protect: BlockProtect,
+    power_cut_after: Option<usize>,
+    bytes_written: usize,
+}
+
+impl SimEeprom {
+    /// A factory fresh part, every byte erased to 0xFF.
+    pub fn new(device: Device) -> SimEeprom {
+        assert!(device.capacity <= MAX_CAPACITY);
+        SimEeprom {
+            device,
+            memory: [0xFF; MAX_CAPACITY],
+            protect: BlockProtect::None,
+            power_cut_after: None,
+            bytes_written: 0,
+        }
+    }
+
+    /// Let `bytes` more bytes reach the array, then drop every write until power is restored.
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.power_cut_after = Some(bytes);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.power_cut_after = None;
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.power_cut_after != Some(0)
+    }
+
+    /// Total bytes that actually reached the array.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.device.capacity]
+    }
+}
+
+impl Default for SimEeprom {
+    /// The board's own part, also the smallest.
+    fn default() -> Self {
+        SimEeprom::new(Device::AT25010B)
+    }
+}
+
+impl Storage for SimEeprom {
+    fn device(&self) -> Device {
+        self.device
+    }
+
+    fn read_bytes(&mut self, address: u16, buf: &mut [u8]) {
+        let start = address as usize;
+        buf.copy_from_slice(&self.memory()[start..start + buf.len()]);
+    }
+
+    fn write_bytes(&mut self, address: u16, data: &[u8]) -> Result<(), Error> {
+        for (i, byte) in data.iter().enumerate() {
+            let address = address + i as u16;
+            if self.protect.protects(self.device.capacity, address) {
+                return Err(Error::WriteProtected(address));
+            }
+            match self.power_cut_after {
+                Some(0) => return Ok(()),
+                Some(ref mut left) => *left -= 1,
+                None => {}
+            }
+            self.memory[address as usize] = *byte;
+            self.bytes_written += 1;
+        }
+        Ok(())
+    }
+
+    fn block_protect(&mut self) -> BlockProtect {
+        self.protect
+    }
+
+    fn set_block_protect(&mut self, protect: BlockProtect) {
+        if self.is_powered() {
+            self.protect = protect;
+        }
+    }
+}
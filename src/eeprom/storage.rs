@@ -0,0 +1,132 @@
+//! What the layers above the driver see of the chip
+//!
+//! The part geometry, the `Storage` trait and CRC-8. Nothing here touches the SPI bus, so the block,
+//! key store and config code built on it also runs off target.
+
+/// Rated write cycles per byte, the same for every part in the family.
+pub const ENDURANCE: u32 = 1_000_000;
+
+/// Geometry of one AT25xxx part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    /// Size of the array in bytes.
+    pub capacity: usize,
+    /// A single write cycle can program up to a page, as long as it does not cross a page boundary.
+    pub page_size: usize,
+    /// Address bytes sent after the opcode.
+    pub address_bytes: usize,
+    /// The 4-Kbit part sends the ninth address bit as bit 3 of the READ and WRITE opcodes.
+    pub a8_in_opcode: bool,
+}
+
+// A board uses a single part, so most of these are unused in any given image.
+#[allow(dead_code)]
+impl Device {
+    pub const AT25010B: Device = Device::new(128, 8, 1, false);
+    pub const AT25020B: Device = Device::new(256, 8, 1, false);
+    pub const AT25040B: Device = Device::new(512, 8, 1, true);
+    pub const AT25080B: Device = Device::new(1024, 32, 2, false);
+    pub const AT25160B: Device = Device::new(2048, 32, 2, false);
+    pub const AT25320B: Device = Device::new(4096, 32, 2, false);
+    pub const AT25640B: Device = Device::new(8192, 32, 2, false);
+    pub const AT25128B: Device = Device::new(16384, 64, 2, false);
+    pub const AT25256B: Device = Device::new(32768, 64, 2, false);
+}
+
+impl Device {
+    pub const fn new(
+        capacity: usize,
+        page_size: usize,
+        address_bytes: usize,
+        a8_in_opcode: bool,
+    ) -> Device {
+        Device {
+            capacity,
+            page_size,
+            address_bytes,
+            a8_in_opcode,
+        }
+    }
+}
+
+/// Byte level access to the array. Implemented by the SPI driver and by the simulator so the layers
+/// above can run against either.
+pub trait Storage {
+    fn device(&self) -> Device;
+    fn read_bytes(&mut self, address: u16, buf: &mut [u8]);
+    fn write_bytes(&mut self, address: u16, data: &[u8]) -> Result<(), Error>;
+    fn block_protect(&mut self) -> BlockProtect;
+    fn set_block_protect(&mut self, protect: BlockProtect);
+}
+
+impl<T: Storage + ?Sized> Storage for &mut T {
+    fn device(&self) -> Device {
+        (**self).device()
+    }
+
+    fn read_bytes(&mut self, address: u16, buf: &mut [u8]) {
+        (**self).read_bytes(address, buf)
+    }
+
+    fn write_bytes(&mut self, address: u16, data: &[u8]) -> Result<(), Error> {
+        (**self).write_bytes(address, data)
+    }
+
+    fn block_protect(&mut self) -> BlockProtect {
+        (**self).block_protect()
+    }
+
+    fn set_block_protect(&mut self, protect: BlockProtect) {
+        (**self).set_block_protect(protect)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The address lies inside the range locked by the BP0/BP1 bits.
+    WriteProtected(u16),
+}
+
+/// Write protection selected by the BP1/BP0 bits of the status register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockProtect {
+    None,
+    UpperQuarter, // 0x60 - 0x7F on the AT25010B
+    UpperHalf,    // 0x40 - 0x7F on the AT25010B
+    All,          // 0x00 - 0x7F on the AT25010B
+}
+
+impl BlockProtect {
+    /// First protected address on a part of `capacity` bytes, if any.
+    pub const fn start(self, capacity: usize) -> Option<u16> {
+        match self {
+            BlockProtect::None => None,
+            BlockProtect::UpperQuarter => Some((capacity - capacity / 4) as u16),
+            BlockProtect::UpperHalf => Some((capacity / 2) as u16),
+            BlockProtect::All => Some(0x00),
+        }
+    }
+
+    pub const fn protects(self, capacity: usize, address: u16) -> bool {
+        match self.start(capacity) {
+            Some(start) => address >= start,
+            None => false,
+        }
+    }
+}
+
+/// CRC-8 with the SMBus polynomial (x^8 + x^2 + x + 1), used by every record kept on the chip.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,101 @@
+//! Encrypted payloads
+//!
+//! An `EncryptedPayload` carries a postcard-encoded value, zero padded to whole blocks and CBC
+//! encrypted with an IV built from the nonce and the length. `len` says where the plaintext ends in
+//! the last block. The tag covers the nonce, the length and the ciphertext, so a corrupted or forged
+//! payload is rejected before anything is decrypted.
+
+use heapless::Vec;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::crypt::{self, Secret, AES_BLOCK_SIZE};
+
+#[cfg(test)]
+pub mod tests;
+
+/// Largest plaintext a payload carries, a whole number of blocks. A `Data::Response` carrying a
+/// `WearReport`, the largest message that is ever sealed, takes up to 50 bytes. Sealed, that no
+/// longer fits a frame and goes out in fragments.
+pub const MAX_PLAINTEXT: usize = 7 * AES_BLOCK_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The value does not encode into `MAX_PLAINTEXT` bytes.
+    TooLarge,
+    /// `len` does not fit the ciphertext, or the ciphertext is not whole blocks.
+    Length,
+    /// The tag does not match, wrong key or the payload was altered.
+    Tag,
+    /// Authentic, but not the type the receiver asked for.
+    Deserialize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncryptedPayload {
+    /// Plaintext bytes in `ciphertext`, the rest of the last block is padding.
+    pub len: u8,
+    /// Must not repeat under one key, it is what makes the IV unique.
+    pub nonce: u32,
+    pub tag: [u8; 8],
+    pub ciphertext: Vec<u8, MAX_PLAINTEXT>,
+}
+
+impl EncryptedPayload {
+    pub fn seal<T: Serialize>(key: &[u8; 8], nonce: u32, value: &T) -> Result<Self, Error> {
+        let mut plaintext = Secret::new([0u8; MAX_PLAINTEXT]);
+        let len = postcard::to_slice(value, &mut plaintext[..])
+            .map_err(|_| Error::TooLarge)?
+            .len();
+        let padded = len.next_multiple_of(AES_BLOCK_SIZE);
+
+        // MAX_PLAINTEXT is whole blocks, so the padded length always fits.
+        let mut ciphertext = Vec::from_slice(&plaintext[..padded]).unwrap();
+        crypt::aes_wrap(key, &iv(nonce, len as u8), &mut ciphertext);
+        Ok(EncryptedPayload {
+            len: len as u8,
+            nonce,
+            tag: tag(key, nonce, len as u8, &ciphertext),
+            ciphertext,
+        })
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, key: &[u8; 8]) -> Result<T, Error> {
+        let (len, size) = (self.len as usize, self.ciphertext.len());
+        if !size.is_multiple_of(AES_BLOCK_SIZE) || len > size || size - len >= AES_BLOCK_SIZE {
+            return Err(Error::Length);
+        }
+        // fold the whole difference so the time taken does not say how much of the tag matched.
+        let expected = tag(key, self.nonce, self.len, &self.ciphertext);
+        if expected
+            .iter()
+            .zip(self.tag)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            != 0
+        {
+            return Err(Error::Tag);
+        }
+
+        let mut plaintext = Secret::new([0u8; MAX_PLAINTEXT]);
+        plaintext[..size].copy_from_slice(&self.ciphertext);
+        crypt::aes_unwrap(key, &iv(self.nonce, self.len), &mut plaintext[..size]);
+        postcard::from_bytes(&plaintext[..len]).map_err(|_| Error::Deserialize)
+    }
+}
+
+/// The header block, which doubles as the IV.
+fn iv(nonce: u32, len: u8) -> [u8; AES_BLOCK_SIZE] {
+    let mut iv = [0u8; AES_BLOCK_SIZE];
+    iv[..4].copy_from_slice(&nonce.to_le_bytes());
+    iv[4] = len;
+    iv
+}
+
+/// Tag over the header block and the ciphertext.
+fn tag(key: &[u8; 8], nonce: u32, len: u8, ciphertext: &[u8]) -> [u8; 8] {
+    let mut tagged = [0u8; AES_BLOCK_SIZE + MAX_PLAINTEXT];
+    let size = AES_BLOCK_SIZE + ciphertext.len();
+    tagged[..AES_BLOCK_SIZE].copy_from_slice(&iv(nonce, len));
+    tagged[AES_BLOCK_SIZE..size].copy_from_slice(ciphertext);
+    crypt::mac(key, &tagged[..size])
+}
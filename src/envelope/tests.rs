@@ -0,0 +1,215 @@
+use postcard::accumulator::{CobsAccumulator, FeedResult};
+
+use super::*;
+use crate::config::{Config, Role};
+use crate::filter::{Filter, Sampling};
+use crate::fragment::MAX_MESSAGE;
+use crate::messages::*;
+use crate::temperature::{Calibration, CalibrationPoint};
+
+const KEY: [u8; 8] = [0x1B, 0x2C, 0x3D, 0x4E, 0x5F, 0x60, 0x71, 0x82];
+const OTHER_KEY: [u8; 8] = [0x82, 0x71, 0x60, 0x5F, 0x4E, 0x3D, 0x2C, 0x1B];
+
+#[test]
+fn every_sealed_message_opens_to_what_was_sent() {
+    for (i, data) in samples().iter().enumerate() {
+        assert!(!data.is_link_setup(), "sample {i} is never sealed");
+        let opened = round_trip(i, data);
+        assert_eq!(encode(&opened), encode(data), "sample {i}");
+    }
+}
+
+/// Seal `data`, frame it as one message and back, and open it. On the line the largest are
+/// fragmented, `fragment` covers that.
+fn round_trip(i: usize, data: &Data) -> Data {
+    let sealed = Encrypted::seal(&KEY, i as u32, data).unwrap();
+    let message = Message {
+        id: i as u8,
+        data: Data::Encrypted(sealed),
+    };
+
+    let mut buffer = [0u8; MAX_MESSAGE];
+    let frame = postcard::to_slice_cobs(&message, &mut buffer).unwrap();
+    let mut accumulator = CobsAccumulator::<256>::new();
+    let FeedResult::Success { data: message, .. } = accumulator.feed::<Message>(frame) else {
+        panic!("sample {i} did not survive the framing");
+    };
+    let Data::Encrypted(sealed) = message.data else {
+        panic!("sample {i} came back unsealed");
+    };
+    sealed.open(&KEY).unwrap()
+}
+
+/// Postcard bytes, compared instead of the values so NaN and the sign of zero count too.
+fn encode(data: &Data) -> heapless::Vec<u8, 64> {
+    postcard::to_vec(data).unwrap()
+}
+
+/// One of each message the link seals, at the extremes of its fields.
+pub fn samples() -> [Data; 35] {
+    let config = Config {
+        baud_rate: u32::MAX,
+        pwm_khz: u32::MAX,
+        temp_threshold: i16::MIN,
+        role: Role::Sensor {
+            period_ms: u16::MAX,
+        },
+        sampling: Sampling {
+            oversample: u8::MAX,
+            reject_outliers: false,
+            filter: Filter::Median(u8::MAX),
+        },
+    };
+    let calibration = Calibration {
+        low: CalibrationPoint {
+            measured: i16::MIN,
+            actual: i16::MIN,
+        },
+        high: CalibrationPoint {
+            measured: i16::MAX,
+            actual: i16::MAX,
+        },
+    };
+    let wipe = WipeReport {
+        wiped: u8::MAX,
+        failed: u8::MAX,
+    };
+    let wear = WearReport {
+        slots: [u32::MAX; 4],
+        staging: u32::MAX,
+        endurance: u32::MAX,
+        config: u32::MAX,
+        calibration: u32::MAX,
+        log: u32::MAX,
+    };
+    let response = |command, result| {
+        Data::Response(Response {
+            request_id: u8::MAX,
+            command,
+            result,
+        })
+    };
+    let log = |event| {
+        Data::Log(LogEntry {
+            seq: u32::MAX,
+            millis: u32::MAX,
+            event,
+        })
+    };
+    [
+        Data::Temperature(Temperature { temp: 21.5 }),
+        Data::Temperature(Temperature { temp: -0.0 }),
+        Data::Temperature(Temperature { temp: f32::MAX }),
+        Data::Temperature(Temperature { temp: f32::NAN }),
+        Data::Status(Status::KeysWiped(wipe.clone())),
+        Data::Status(Status::LogDumped(u32::MAX)),
+        Data::Status(Status::SensorFailed),
+        Data::Status(Status::Error(ErrorReport {
+            code: ErrorCode::Busy,
+            message_id: u8::MAX,
+        })),
+        Data::Command(Command::DeleteAESKey),
+        Data::Command(Command::WipeAllKeys),
+        Data::Command(Command::ReportWear),
+        Data::Command(Command::ReportCacheStats),
+        Data::Command(Command::SetConfig(config.clone())),
+        Data::Command(Command::GetConfig),
+        Data::Command(Command::DumpLog),
+        Data::Command(Command::SetCalibration(calibration)),
+        Data::Command(Command::GetCalibration),
+        Data::Command(Command::ReadTemperature),
+        Data::Command(Command::SelfTest),
+        Data::Wear(wear.clone()),
+        Data::CacheStats(CacheStats {
+            hits: u32::MAX,
+            misses: u32::MAX,
+            bytes_saved: u32::MAX,
+        }),
+        Data::Config(config.clone()),
+        Data::Config(Config::default()),
+        log(Event::Boot),
+        log(Event::OverTemperature(f32::MIN)),
+        log(Event::DecryptFailed),
+        log(Event::KeysWiped(wipe)),
+        Data::Calibration(calibration),
+        Data::Calibration(Calibration::default()),
+        Data::Reading(Reading {
+            measured: f32::MIN,
+            celsius: f32::INFINITY,
+        }),
+        Data::Reading(Reading {
+            measured: 0.0,
+            celsius: 0.0,
+        }),
+        response(CommandId::ReportWear, Ok(Reply::Wear(wear))),
+        response(CommandId::SetConfig, Ok(Reply::Config(config))),
+        response(CommandId::DumpLog, Ok(Reply::LogDumped(u32::MAX))),
+        response(
+            CommandId::SelfTest,
+            Ok(Reply::SelfTest(SelfTestReport {
+                supply_mv: u16::MAX,
+                failed: u8::MAX,
+            })),
+        ),
+    ]
+}
+
+fn good() -> EncryptedPayload {
+    EncryptedPayload::seal(&KEY, 7, &Temperature { temp: 21.5 }).unwrap()
+}
+
+#[test]
+fn the_wrong_key_is_refused() {
+    assert_eq!(
+        good().open::<Temperature>(&OTHER_KEY).err(),
+        Some(Error::Tag)
+    );
+}
+
+#[test]
+fn any_altered_bit_is_refused() {
+    let good = good();
+    for i in 0..good.ciphertext.len() {
+        let mut bad = good.clone();
+        bad.ciphertext[i] ^= 0x01;
+        assert_eq!(
+            bad.open::<Temperature>(&KEY).err(),
+            Some(Error::Tag),
+            "byte {i}"
+        );
+    }
+    for i in 0..good.tag.len() {
+        let mut bad = good.clone();
+        bad.tag[i] ^= 0x80;
+        assert_eq!(
+            bad.open::<Temperature>(&KEY).err(),
+            Some(Error::Tag),
+            "tag byte {i}"
+        );
+    }
+
+    // the header is covered too, replaying under another nonce does not pass.
+    let mut bad = good.clone();
+    bad.nonce += 1;
+    assert_eq!(bad.open::<Temperature>(&KEY).err(), Some(Error::Tag));
+}
+
+#[test]
+fn bad_lengths_are_refused() {
+    let mut bad = good();
+    bad.len = bad.ciphertext.len() as u8 + 1;
+    assert_eq!(bad.open::<Temperature>(&KEY).err(), Some(Error::Length));
+
+    let mut bad = good();
+    bad.ciphertext.pop();
+    assert_eq!(bad.open::<Temperature>(&KEY).err(), Some(Error::Length));
+}
+
+#[test]
+fn a_value_too_large_is_not_sealed() {
+    let too_large = [[0u8; MAX_PLAINTEXT / 2]; 3];
+    assert_eq!(
+        EncryptedPayload::seal(&KEY, 0, &too_large).err(),
+        Some(Error::TooLarge)
+    );
+}
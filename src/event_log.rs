@@ -0,0 +1,146 @@
+//! Append-only event log in EEPROM
+//!
+//! The log fills the space between the wear counts and the identity slot with fixed size entries
+//! and wraps around when it is full, overwriting the oldest one. Each entry is
+//! `[seq (4 bytes), len, payload.., crc]`, the payload being the postcard encoding of the boot
+//! time and the event. The sequence number keeps counting across resets, so at boot the entry with
+//! the highest one marks where to continue.
+//!
+//! Room for `MIN_ENTRIES` is checked at compile time on the AT25040B, larger parts get more. The
+//! AT25020B has room for a single entry and the AT25010B for none at all.
+
+use crate::eeprom::{self, crc8, Device, Storage};
+use crate::key_store;
+use crate::messages::{Event, LogEntry};
+use crate::wear;
+
+const ENTRY_LEN: usize = 16;
+const HEADER_LEN: usize = 5; // seq + len
+const CRC_LEN: usize = 1;
+const MAX_PAYLOAD_LEN: usize = ENTRY_LEN - HEADER_LEN - CRC_LEN;
+
+const START: u16 = wear::END as u16;
+
+/// Entries the AT25040B and every larger part have room for.
+pub const MIN_ENTRIES: usize = 4;
+
+// MIN_ENTRIES on the AT25040B, and a single entry on the AT25020B.
+const _: () = {
+    assert!(
+        START as usize + MIN_ENTRIES * ENTRY_LEN <= key_store::free_end(Device::AT25040B.capacity)
+    );
+    assert!(START as usize + ENTRY_LEN <= key_store::free_end(Device::AT25020B.capacity));
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Eeprom(eeprom::Error),
+    /// The encoded event does not fit in an entry.
+    TooLarge,
+    /// The part has no room for a single entry.
+    NoRoom,
+}
+
+impl From<eeprom::Error> for Error {
+    fn from(err: eeprom::Error) -> Self {
+        Error::Eeprom(err)
+    }
+}
+
+pub struct EventLog {
+    entries: u16,
+    /// Index the next entry goes to.
+    next: u16,
+    seq: u32,
+}
+
+impl EventLog {
+    /// Scan the log for the newest entry. Fails with `NoRoom` on the AT25010B.
+    pub fn new<S: Storage>(storage: &mut S) -> Result<EventLog, Error> {
+        let end = key_store::free_end(storage.device().capacity);
+        let entries = (end.saturating_sub(START as usize) / ENTRY_LEN) as u16;
+        if entries == 0 {
+            return Err(Error::NoRoom);
+        }
+        let mut log = EventLog {
+            entries,
+            next: 0,
+            seq: 0,
+        };
+
+        let mut newest = None;
+        for index in 0..log.entries {
+            if let Some(entry) = log.read(storage, index) {
+                if newest.is_none_or(|(_, seq)| entry.seq >= seq) {
+                    newest = Some((index, entry.seq));
+                }
+            }
+        }
+        if let Some((index, seq)) = newest {
+            log.next = (index + 1) % log.entries;
+            log.seq = seq.wrapping_add(1);
+        }
+        Ok(log)
+    }
+
+    pub fn append<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        millis: u32,
+        event: &Event,
+    ) -> Result<(), Error> {
+        let mut entry = [0xFFu8; ENTRY_LEN];
+        let len = postcard::to_slice(
+            &(millis, event),
+            &mut entry[HEADER_LEN..HEADER_LEN + MAX_PAYLOAD_LEN],
+        )
+        .map_err(|_| Error::TooLarge)?
+        .len();
+        entry[..4].copy_from_slice(&self.seq.to_le_bytes());
+        entry[4] = len as u8;
+        let body_len = HEADER_LEN + len;
+        entry[body_len] = crc8(&entry[..body_len]);
+
+        storage.write_bytes(Self::address(self.next), &entry)?;
+        self.next = (self.next + 1) % self.entries;
+        self.seq = self.seq.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Call `f` with every readable entry, oldest first. Torn entries are skipped.
+    pub fn for_each<S: Storage>(&self, storage: &mut S, mut f: impl FnMut(LogEntry)) {
+        for i in 0..self.entries {
+            let index = (self.next + i) % self.entries;
+            if let Some(entry) = self.read(storage, index) {
+                f(entry);
+            }
+        }
+    }
+
+    fn read<S: Storage>(&self, storage: &mut S, index: u16) -> Option<LogEntry> {
+        let mut entry = [0u8; ENTRY_LEN];
+        storage.read_bytes(Self::address(index), &mut entry);
+
+        let len = entry[4] as usize;
+        if len > MAX_PAYLOAD_LEN {
+            return None;
+        }
+        let body_len = HEADER_LEN + len;
+        if entry[body_len] != crc8(&entry[..body_len]) {
+            return None;
+        }
+        let (millis, event) = postcard::from_bytes(&entry[HEADER_LEN..body_len]).ok()?;
+        Some(LogEntry {
+            seq: u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]),
+            millis,
+            event,
+        })
+    }
+
+    fn address(index: u16) -> u16 {
+        START + index * ENTRY_LEN as u16
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+use crate::eeprom::sim::SimEeprom;
+use crate::eeprom::Device;
+
+fn entries(log: &EventLog, sim: &mut SimEeprom) -> std::vec::Vec<LogEntry> {
+    let mut entries = std::vec::Vec::new();
+    log.for_each(sim, |entry| entries.push(entry));
+    entries
+}
+
+#[test]
+fn entries_read_back_after_a_reboot() {
+    let mut sim = SimEeprom::new(Device::AT25040B);
+    let mut log = EventLog::new(&mut sim).unwrap();
+    log.append(&mut sim, 10, &Event::Boot).unwrap();
+    log.append(&mut sim, 20, &Event::DecryptFailed).unwrap();
+
+    let log = EventLog::new(&mut sim).unwrap();
+    let entries = entries(&log, &mut sim);
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[0].seq, entries[0].millis), (0, 10));
+    assert!(matches!(entries[0].event, Event::Boot));
+    assert_eq!((entries[1].seq, entries[1].millis), (1, 20));
+    assert!(matches!(entries[1].event, Event::DecryptFailed));
+}
+
+#[test]
+fn a_full_log_overwrites_the_oldest_entry() {
+    let mut sim = SimEeprom::new(Device::AT25040B);
+    let room = EventLog::new(&mut sim).unwrap().entries as u32;
+    let total = room + 2;
+    for millis in 0..total {
+        // a reboot between every entry, the sequence has to carry on from the chip.
+        let mut log = EventLog::new(&mut sim).unwrap();
+        log.append(&mut sim, millis, &Event::Boot).unwrap();
+    }
+
+    let log = EventLog::new(&mut sim).unwrap();
+    let seqs: std::vec::Vec<u32> = entries(&log, &mut sim).iter().map(|e| e.seq).collect();
+    let kept = total - room;
+    assert_eq!(seqs, (kept..total).collect::<std::vec::Vec<_>>());
+}
+
+/// The board's own part has no room for a log, it is refused without a byte being touched.
+#[test]
+fn a_part_without_room_is_refused() {
+    let mut sim = SimEeprom::new(Device::AT25010B);
+    assert_eq!(EventLog::new(&mut sim).err(), Some(Error::NoRoom));
+    assert_eq!(sim.bytes_written(), 0);
+    assert!(sim.memory().iter().all(|&byte| byte == 0xFF));
+}
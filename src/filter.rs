@@ -0,0 +1,160 @@
+//! Sampling pipeline for analog readings
+//!
+//! Each reading is a burst of `oversample` conversions, averaged after optionally dropping the
+//! highest and the lowest one, then passed through a filter that remembers earlier readings.
+//! Everything is integer math on Q4 counts, ADC counts with 4 fractional bits, since the M0 has no
+//! FPU. A 12 bit count still fits a `u16` that way.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+mod bench;
+#[cfg(test)]
+mod tests;
+
+pub const FRACTION_BITS: u32 = 4;
+pub const MAX_OVERSAMPLE: u8 = 16;
+pub const MAX_WINDOW: u8 = 8;
+
+// extra precision the EMA keeps internally so small steps do not vanish in the shift.
+const EMA_BITS: u32 = 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    None,
+    /// Mean of the last n readings.
+    MovingAverage(u8),
+    /// Exponential moving average, each reading moves the output by 1/2^k of the difference.
+    Ema(u8),
+    /// Median of the last n readings, shrugs off single spikes.
+    Median(u8),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Sampling {
+    /// Conversions per reading, up to `MAX_OVERSAMPLE`.
+    pub oversample: u8,
+    /// Drop the highest and the lowest conversion of each burst, needs at least 3.
+    pub reject_outliers: bool,
+    pub filter: Filter,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            oversample: 8,
+            reject_outliers: true,
+            filter: Filter::Ema(2),
+        }
+    }
+}
+
+pub struct Pipeline {
+    sampling: Sampling,
+    window: [u16; MAX_WINDOW as usize],
+    filled: usize,
+    next: usize,
+    ema: Option<u32>,
+}
+
+impl Pipeline {
+    /// Out of range settings are clamped.
+    pub fn new(sampling: Sampling) -> Pipeline {
+        let mut pipeline = Pipeline {
+            sampling,
+            window: [0; MAX_WINDOW as usize],
+            filled: 0,
+            next: 0,
+            ema: None,
+        };
+        pipeline.set_sampling(sampling);
+        pipeline
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    /// Change the settings, this forgets the filter history.
+    pub fn set_sampling(&mut self, mut sampling: Sampling) {
+        sampling.oversample = sampling.oversample.clamp(1, MAX_OVERSAMPLE);
+        sampling.filter = match sampling.filter {
+            Filter::MovingAverage(n) => Filter::MovingAverage(n.clamp(1, MAX_WINDOW)),
+            Filter::Median(n) => Filter::Median(n.clamp(1, MAX_WINDOW)),
+            Filter::Ema(k) => Filter::Ema(k.min(EMA_BITS as u8)),
+            Filter::None => Filter::None,
+        };
+        self.sampling = sampling;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.filled = 0;
+        self.next = 0;
+        self.ema = None;
+    }
+
+    /// Take one reading with `read` and filter it. Returns Q4 counts, `None` if a conversion
+    /// failed, in which case the filter is left as it was.
+    pub fn sample(&mut self, read: impl FnMut() -> Option<u16>) -> Option<u16> {
+        let reading = self.oversample(read)?;
+        Some(self.filter(reading))
+    }
+
+    /// Average one burst of conversions into Q4 counts.
+    pub fn oversample(&self, mut read: impl FnMut() -> Option<u16>) -> Option<u16> {
+        let count = self.sampling.oversample as u32;
+        let (mut sum, mut min, mut max) = (0u32, u16::MAX, 0u16);
+        for _ in 0..count {
+            let counts = read()?;
+            sum += counts as u32;
+            min = min.min(counts);
+            max = max.max(counts);
+        }
+        let (sum, count) = if self.sampling.reject_outliers && count >= 3 {
+            (sum - min as u32 - max as u32, count - 2)
+        } else {
+            (sum, count)
+        };
+        Some((((sum << FRACTION_BITS) + count / 2) / count) as u16)
+    }
+
+    /// Feed one Q4 reading through the filter.
+    pub fn filter(&mut self, reading: u16) -> u16 {
+        match self.sampling.filter {
+            Filter::None => reading,
+            Filter::MovingAverage(n) => {
+                let window = self.push(reading, n as usize);
+                let sum: u32 = window.iter().map(|&x| x as u32).sum();
+                ((sum + window.len() as u32 / 2) / window.len() as u32) as u16
+            }
+            Filter::Median(n) => {
+                let mut sorted = [0u16; MAX_WINDOW as usize];
+                let window = self.push(reading, n as usize);
+                let sorted = &mut sorted[..window.len()];
+                sorted.copy_from_slice(window);
+                sorted.sort_unstable();
+                sorted[sorted.len() / 2]
+            }
+            Filter::Ema(k) => {
+                let x = (reading as u32) << EMA_BITS;
+                let y = match self.ema {
+                    // y + (x - y) / 2^k, kept unsigned by splitting on the sign.
+                    Some(y) if x >= y => y + ((x - y) >> k),
+                    Some(y) => y - ((y - x) >> k),
+                    None => x,
+                };
+                self.ema = Some(y);
+                ((y + (1 << (EMA_BITS - 1))) >> EMA_BITS) as u16
+            }
+        }
+    }
+
+    /// Add a reading to the window of the last `n` and return the readings it holds.
+    fn push(&mut self, reading: u16, n: usize) -> &[u16] {
+        self.window[self.next] = reading;
+        self.next = (self.next + 1) % n;
+        self.filled = (self.filled + 1).min(n);
+        &self.window[..self.filled]
+    }
+}
@@ -0,0 +1,104 @@
+//! Synthetic signals for checking the pipeline off target
+//!
+//! `step_response` drives a pipeline with a noisy step and reports how long it took to settle and
+//! how much noise is left afterwards, so settings can be compared without a board. The noise is a
+//! fixed pseudo-random sequence, the same seed always gives the same figures. Only the host tests
+//! build it.
+
+use super::{Pipeline, Sampling, FRACTION_BITS};
+
+/// Uniform noise of up to `amplitude` counts either way, plus a spike of `spike` counts every
+/// `spike_every` conversions to exercise outlier rejection.
+pub struct Noise {
+    state: u32,
+    amplitude: u16,
+    spike: u16,
+    spike_every: u32,
+    count: u32,
+}
+
+impl Noise {
+    pub fn new(seed: u32, amplitude: u16, spike: u16, spike_every: u32) -> Noise {
+        Noise {
+            state: seed,
+            amplitude,
+            spike,
+            spike_every,
+            count: 0,
+        }
+    }
+
+    /// Add noise to a clean conversion, clamped to the 12 bit range.
+    pub fn apply(&mut self, counts: u16) -> u16 {
+        // Numerical Recipes LCG, the top bits are good enough for a test signal.
+        self.state = self
+            .state
+            .wrapping_mul(1_664_525)
+            .wrapping_add(1_013_904_223);
+        let span = 2 * self.amplitude as u32 + 1;
+        let mut noisy = counts as i32 + ((self.state >> 16) % span) as i32 - self.amplitude as i32;
+        self.count += 1;
+        if self.spike_every != 0 && self.count.is_multiple_of(self.spike_every) {
+            noisy += self.spike as i32;
+        }
+        noisy.clamp(0, 4095) as u16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Figures {
+    /// Readings after the step until the output came within `tolerance` of the target for good,
+    /// `None` if it never did.
+    pub settling: Option<usize>,
+    /// RMS error over the settled readings, in Q4 counts.
+    pub noise_rms: u32,
+    /// Largest error over the settled readings, in Q4 counts.
+    pub peak_error: u32,
+}
+
+/// Settle the pipeline on `from`, step the input to `to` and take `readings` readings. `tolerance`
+/// is in Q4 counts.
+pub fn step_response(
+    sampling: Sampling,
+    from: u16,
+    to: u16,
+    noise: &mut Noise,
+    readings: usize,
+    tolerance: u32,
+) -> Figures {
+    let mut pipeline = Pipeline::new(sampling);
+    for _ in 0..32 {
+        pipeline.sample(|| Some(noise.apply(from)));
+    }
+
+    let target = (to as u32) << FRACTION_BITS;
+    let mut settling = None;
+    let (mut sum_squares, mut settled, mut peak_error) = (0u64, 0u64, 0u32);
+    for reading in 0..readings {
+        let output = pipeline.sample(|| Some(noise.apply(to))).unwrap_or(0) as u32;
+        let error = output.abs_diff(target);
+        if error > tolerance {
+            settling = None;
+            sum_squares = 0;
+            settled = 0;
+            peak_error = 0;
+            continue;
+        }
+        if settling.is_none() {
+            settling = Some(reading);
+        }
+        sum_squares += error as u64 * error as u64;
+        settled += 1;
+        peak_error = peak_error.max(error);
+    }
+
+    let noise_rms = match settled {
+        0 => 0,
+        n => (sum_squares / n).isqrt() as u32,
+    };
+    Figures {
+        settling,
+        noise_rms,
+        peak_error,
+    }
+}
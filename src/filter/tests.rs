@@ -0,0 +1,147 @@
+use super::bench::{step_response, Figures, Noise};
+use super::*;
+
+const FROM: u16 = 1_000;
+const TO: u16 = 2_000;
+// a quarter of a count.
+const TOLERANCE: u32 = 4;
+
+fn sampling(oversample: u8, reject_outliers: bool, filter: Filter) -> Sampling {
+    Sampling {
+        oversample,
+        reject_outliers,
+        filter,
+    }
+}
+
+fn respond(sampling: Sampling, noise: &mut Noise, readings: usize, tolerance: u32) -> Figures {
+    step_response(sampling, FROM, TO, noise, readings, tolerance)
+}
+
+fn quiet() -> Noise {
+    Noise::new(1, 0, 0, 0)
+}
+
+#[test]
+fn a_clean_step_passes_straight_through_without_a_filter() {
+    let figures = respond(
+        sampling(1, false, Filter::None),
+        &mut quiet(),
+        64,
+        TOLERANCE,
+    );
+    assert_eq!(
+        figures,
+        Figures {
+            settling: Some(0),
+            noise_rms: 0,
+            peak_error: 0,
+        }
+    );
+}
+
+#[test]
+fn a_slower_ema_takes_longer_to_settle() {
+    let ema = |k| {
+        respond(
+            sampling(1, false, Filter::Ema(k)),
+            &mut quiet(),
+            256,
+            TOLERANCE,
+        )
+    };
+    let (fast, slow) = (ema(1).settling.unwrap(), ema(4).settling.unwrap());
+    assert!(fast < slow, "{fast} vs {slow}");
+}
+
+#[test]
+fn a_moving_average_settles_after_its_window() {
+    let moving_average = sampling(1, false, Filter::MovingAverage(4));
+    let figures = respond(moving_average, &mut quiet(), 64, TOLERANCE);
+    assert_eq!(figures.settling, Some(3));
+}
+
+#[test]
+fn oversampling_and_filtering_bring_the_noise_down() {
+    // wide enough for the raw readings to count as settled too.
+    let tolerance = 16 << FRACTION_BITS;
+    let noise = || Noise::new(7, 8, 0, 0);
+    let raw = respond(
+        sampling(1, false, Filter::None),
+        &mut noise(),
+        64,
+        tolerance,
+    );
+    let smooth = sampling(8, false, Filter::MovingAverage(8));
+    let filtered = respond(smooth, &mut noise(), 64, tolerance);
+    assert_eq!(raw.settling, Some(0));
+    assert!(filtered.settling.is_some(), "{filtered:?}");
+    assert!(
+        filtered.noise_rms * 4 < raw.noise_rms,
+        "{filtered:?} vs {raw:?}"
+    );
+    assert!(
+        filtered.peak_error * 4 < raw.peak_error,
+        "{filtered:?} vs {raw:?}"
+    );
+}
+
+#[test]
+fn outlier_rejection_drops_a_spike_in_each_burst() {
+    let spikes = || Noise::new(3, 0, 400, 8);
+    let kept = respond(
+        sampling(8, false, Filter::None),
+        &mut spikes(),
+        64,
+        TOLERANCE,
+    );
+    let rejected = respond(
+        sampling(8, true, Filter::None),
+        &mut spikes(),
+        64,
+        TOLERANCE,
+    );
+    assert!(kept.settling.is_none(), "{kept:?}");
+    assert_eq!(rejected.settling, Some(0));
+    assert_eq!(rejected.peak_error, 0);
+}
+
+#[test]
+fn a_median_ignores_a_single_spike() {
+    let mut pipeline = Pipeline::new(sampling(1, false, Filter::Median(3)));
+    let mut out = 0;
+    for counts in [TO, TO, 4_000, TO, TO] {
+        out = pipeline.sample(|| Some(counts)).unwrap();
+    }
+    assert_eq!(out, TO << FRACTION_BITS);
+    assert_eq!(pipeline.sample(|| Some(4_000)), Some(TO << FRACTION_BITS));
+}
+
+#[test]
+fn a_failed_conversion_leaves_the_filter_as_it_was() {
+    let mut pipeline = Pipeline::new(sampling(4, false, Filter::Ema(1)));
+    pipeline.sample(|| Some(FROM));
+    let mut reads = 0;
+    let failed = pipeline.sample(|| {
+        reads += 1;
+        (reads < 3).then_some(TO)
+    });
+    assert_eq!(failed, None);
+    // the EMA picks up from the first reading, not from the aborted burst.
+    assert_eq!(
+        pipeline.sample(|| Some(TO)),
+        Some(((FROM as u32 + TO as u32) << FRACTION_BITS >> 1) as u16)
+    );
+}
+
+#[test]
+fn out_of_range_settings_are_clamped() {
+    let pipeline = Pipeline::new(sampling(0, true, Filter::MovingAverage(200)));
+    assert_eq!(
+        pipeline.sampling(),
+        sampling(1, true, Filter::MovingAverage(MAX_WINDOW))
+    );
+    let pipeline = Pipeline::new(sampling(200, false, Filter::Ema(200)));
+    assert_eq!(pipeline.sampling().oversample, MAX_OVERSAMPLE);
+    assert_eq!(pipeline.sampling().filter, Filter::Ema(EMA_BITS as u8));
+}
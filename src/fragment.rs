@@ -0,0 +1,124 @@
+//! Fragmentation of messages larger than one frame
+//!
+//! A message whose frame would not fit `FRAME_SIZE` is encoded on its own and split into up to
+//! `MAX_FRAGMENTS` fragments, each sent as a `Data::Fragment` under the message's id. Every
+//! fragment but the last is full, so the index alone says where its bytes go.
+//!
+//! The receiver puts one message back together at a time, in a buffer of `MAX_MESSAGE` bytes.
+//! Fragments may arrive out of order or twice. A fragment of another message starts over, and so
+//! does one that comes more than `TIMEOUT_MS` after the first, so a lost fragment only costs the
+//! message it belonged to.
+
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+mod tests;
+
+/// Largest frame on the wire, COBS overhead and delimiter included.
+pub const FRAME_SIZE: usize = 64;
+/// Message bytes per fragment, what is left of a frame after the headers.
+pub const FRAGMENT_PAYLOAD: usize = 48;
+pub const MAX_FRAGMENTS: usize = 4;
+/// Largest encoded `Data` that can be sent at all.
+pub const MAX_MESSAGE: usize = MAX_FRAGMENTS * FRAGMENT_PAYLOAD;
+/// How long the fragments of one message may take to arrive.
+pub const TIMEOUT_MS: u32 = 500;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Fragment {
+    pub index: u8,
+    pub count: u8,
+    pub bytes: Vec<u8, FRAGMENT_PAYLOAD>,
+}
+
+/// The fragments that carry `bytes`, `None` if there are none or too many.
+pub fn split(bytes: &[u8]) -> Option<impl Iterator<Item = Fragment> + '_> {
+    if bytes.is_empty() || bytes.len() > MAX_MESSAGE {
+        return None;
+    }
+    let count = bytes.len().div_ceil(FRAGMENT_PAYLOAD) as u8;
+    Some(
+        bytes
+            .chunks(FRAGMENT_PAYLOAD)
+            .enumerate()
+            .map(move |(index, chunk)| Fragment {
+                index: index as u8,
+                count,
+                // chunks are at most FRAGMENT_PAYLOAD long.
+                bytes: Vec::from_slice(chunk).unwrap(),
+            }),
+    )
+}
+
+pub struct Reassembler {
+    /// Id of the message being put together, `None` when idle.
+    id: Option<u8>,
+    count: u8,
+    /// One bit per fragment index that is in.
+    received: u8,
+    len: usize,
+    started: u32,
+    buffer: [u8; MAX_MESSAGE],
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler::new()
+    }
+}
+
+impl Reassembler {
+    pub const fn new() -> Reassembler {
+        Reassembler {
+            id: None,
+            count: 0,
+            received: 0,
+            len: 0,
+            started: 0,
+            buffer: [0; MAX_MESSAGE],
+        }
+    }
+
+    /// Take a fragment of message `id`, answers with the whole message once the last one is in.
+    /// Fragments that cannot belong to any message are dropped.
+    pub fn push(&mut self, now: u32, id: u8, fragment: &Fragment) -> Option<&[u8]> {
+        let (index, count, size) = (
+            fragment.index as usize,
+            fragment.count as usize,
+            fragment.bytes.len(),
+        );
+        let last = index + 1 == count;
+        if count == 0
+            || count > MAX_FRAGMENTS
+            || index >= count
+            || size == 0
+            || (!last && size != FRAGMENT_PAYLOAD)
+        {
+            return None;
+        }
+
+        let current = self.id == Some(id)
+            && self.count == fragment.count
+            && now.wrapping_sub(self.started) < TIMEOUT_MS;
+        if !current {
+            self.id = Some(id);
+            self.count = fragment.count;
+            self.received = 0;
+            self.started = now;
+        }
+
+        let offset = index * FRAGMENT_PAYLOAD;
+        self.buffer[offset..offset + size].copy_from_slice(&fragment.bytes);
+        self.received |= 1 << index;
+        if last {
+            self.len = offset + size;
+        }
+
+        if self.received.count_ones() as usize != count {
+            return None;
+        }
+        self.id = None;
+        Some(&self.buffer[..self.len])
+    }
+}
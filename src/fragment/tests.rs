@@ -0,0 +1,122 @@
+use super::*;
+use crate::messages::{Data, Message};
+
+fn message(len: usize) -> Vec<u8, MAX_MESSAGE> {
+    (0..len).map(|i| (i * 7 + len) as u8).collect()
+}
+
+fn fragments(bytes: &[u8]) -> Vec<Fragment, MAX_FRAGMENTS> {
+    split(bytes).unwrap().collect()
+}
+
+/// Push `fragments` of message `bytes`, it must come back whole and not before every fragment was
+/// in. A duplicate can start it over, a message of one fragment then completes again.
+fn round_trip<'a>(bytes: &[u8], fragments: impl Iterator<Item = &'a Fragment>) {
+    let mut reassembler = Reassembler::new();
+    let (mut seen, mut completed) = (0u8, false);
+    for fragment in fragments {
+        seen |= 1 << fragment.index;
+        if let Some(whole) = reassembler.push(0, 1, fragment) {
+            assert_eq!(whole, bytes);
+            assert_eq!(seen.count_ones(), fragment.count as u32);
+            completed = true;
+        }
+    }
+    assert!(completed, "{} bytes never completed", bytes.len());
+}
+
+#[test]
+fn every_size_comes_back_in_any_order() {
+    for len in 1..=MAX_MESSAGE {
+        let bytes = message(len);
+        let fragments = fragments(&bytes);
+        round_trip(&bytes, fragments.iter());
+        round_trip(&bytes, fragments.iter().rev());
+        round_trip(&bytes, fragments.iter().flat_map(|f| [f, f]));
+    }
+}
+
+#[test]
+fn empty_and_oversized_messages_do_not_split() {
+    assert!(split(&[]).is_none());
+    assert!(split(&[0; MAX_MESSAGE + 1]).is_none());
+}
+
+#[test]
+fn fragments_after_the_timeout_do_not_complete() {
+    let bytes = message(MAX_MESSAGE);
+    let whole = fragments(&bytes);
+    let (first, rest) = whole.split_first().unwrap();
+    let mut reassembler = Reassembler::new();
+    reassembler.push(0, 1, first);
+    for fragment in rest {
+        assert_eq!(reassembler.push(TIMEOUT_MS, 1, fragment), None);
+    }
+}
+
+#[test]
+fn another_message_replaces_the_one_in_progress() {
+    let bytes = message(MAX_MESSAGE);
+    let whole = fragments(&bytes);
+    let (first, rest) = whole.split_first().unwrap();
+    let mut reassembler = Reassembler::new();
+    reassembler.push(0, 1, first);
+    let other = &fragments(&message(3))[0];
+    assert_eq!(reassembler.push(0, 2, other), Some(&other.bytes[..]));
+    for fragment in rest {
+        assert_eq!(reassembler.push(0, 1, fragment), None);
+    }
+}
+
+#[test]
+fn malformed_fragments_never_complete() {
+    let bytes = message(MAX_MESSAGE);
+    let first = fragments(&bytes)[0].clone();
+    let malformed = [
+        Fragment {
+            index: 1,
+            count: 1,
+            ..first.clone()
+        },
+        Fragment {
+            count: 0,
+            ..first.clone()
+        },
+        Fragment {
+            count: 5,
+            ..first.clone()
+        },
+        Fragment {
+            index: 0,
+            count: 2,
+            bytes: Vec::from_slice(&[0; FRAGMENT_PAYLOAD - 1]).unwrap(),
+        },
+        Fragment {
+            index: 0,
+            count: 1,
+            bytes: Vec::new(),
+        },
+    ];
+    for fragment in &malformed {
+        assert_eq!(
+            Reassembler::new().push(0, 1, fragment),
+            None,
+            "{fragment:?}"
+        );
+    }
+}
+
+#[test]
+fn a_full_fragment_fits_a_frame() {
+    // the worst case for COBS.
+    let message = Message {
+        id: u8::MAX,
+        data: Data::Fragment(Fragment {
+            index: u8::MAX,
+            count: u8::MAX,
+            bytes: Vec::from_slice(&[0xFF; FRAGMENT_PAYLOAD]).unwrap(),
+        }),
+    };
+    let mut frame = [0u8; FRAME_SIZE];
+    assert!(postcard::to_slice_cobs(&message, &mut frame).is_ok());
+}
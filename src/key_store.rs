@@ -0,0 +1,650 @@
+//! Typed key storage on top of the EEPROM
+//!
+//! Every key lives in a named slot with a fixed place in the array. Callers ask for a slot and get
+//! the key type back, they never see an address.
+//!
+//! A slot holds a record: `[tag, len, generation (3 bytes), payload.., crc]`. The tag carries the
+//! format version, a validity flag and the slot index, and the CRC covers everything before it. A
+//! blank or erased record reads back as `None`, anything else that does not check out is reported
+//! as corrupt.
+//!
+//! Updates are double-buffered. The new record is first written to a shared staging area (copy A)
+//! and only then over the slot itself (copy B), each time with the slot's generation bumped. Readers
+//! take whichever complete copy has the higher generation, so a power cut at any byte leaves either
+//! the previous key or the new one, never a mix. There is a single staging area rather than one
+//! per slot because the smallest part only has 128 bytes.
+//!
+//! Live payloads are encrypted before they reach the chip, with a key-encryption key derived from
+//! the MCU's unique ID and the record header as IV. The CRC covers the plaintext, so a part moved
+//! to another board reads back as corrupt rather than handing out garbage keys. Tombstones carry
+//! no key material and are left in the clear.
+//!
+//! Records are cached in RAM. Each slot is read from the chip once, at boot, and every later read
+//! is served from the cache. Writes go through to the chip and refresh the cache.
+
+use crate::crypt::{self, RSAKeyPair, RSAPublicKey, Secret, Zeroize, AES_BLOCK_SIZE};
+use crate::eeprom::{self, crc8, BlockProtect, Device, Storage};
+use crate::messages::{CacheStats, WipeReport};
+
+#[cfg(test)]
+mod tests;
+
+/// Largest key we store, used to size scratch buffers.
+pub const MAX_KEY_LEN: usize = 24;
+
+/// Bump whenever the record layout changes, older records then read back as corrupt.
+pub const FORMAT_VERSION: u8 = 3;
+
+const VALID: u8 = 0b0000_1000;
+const HEADER_LEN: usize = 5; // tag + len + generation
+const CRC_LEN: usize = 1;
+const MAX_RECORD_LEN: usize = HEADER_LEN + MAX_KEY_LEN + CRC_LEN;
+
+/// The 24 bit generation outlasts the ~1M write endurance of the part, so it never wraps.
+const MAX_GENERATION: u32 = 0x00FF_FFFF;
+
+/// Staging copy used for every update, sized for the largest record.
+pub const STAGING_ADDRESS: u16 = 0x32;
+
+/// End of the staging area. The bytes from here up to `free_end` are free for other users.
+pub const STAGING_END: usize = STAGING_ADDRESS as usize + MAX_RECORD_LEN;
+
+const SLOT_COUNT: usize = Slot::ALL.len();
+const CACHE_LEN: usize = SLOT_COUNT * MAX_RECORD_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Eeprom(eeprom::Error),
+    /// The record is not blank but fails the version, length or CRC check, e.g. a torn write.
+    Corrupt(Slot),
+    /// Key material was still found after a wipe.
+    WipeFailed(Slot),
+}
+
+impl From<eeprom::Error> for Error {
+    fn from(err: eeprom::Error) -> Self {
+        Error::Eeprom(err)
+    }
+}
+
+/// The identity key pair sits in the upper quarter so it can be locked once provisioned.
+pub const IDENTITY_PROTECTION: BlockProtect = BlockProtect::UpperQuarter;
+
+/// End of the free bytes above the staging area on a part of `capacity` bytes, where the identity
+/// slot starts.
+pub const fn free_end(capacity: usize) -> usize {
+    match IDENTITY_PROTECTION.start(capacity) {
+        Some(start) => start as usize,
+        None => capacity,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    OwnAesKey,
+    OwnKeyPair,
+    PeerAesKey,
+    PeerPublicKey,
+}
+
+impl Slot {
+    pub const ALL: [Slot; 4] = [
+        Slot::OwnAesKey,
+        Slot::OwnKeyPair,
+        Slot::PeerAesKey,
+        Slot::PeerPublicKey,
+    ];
+
+    /// Session keys sit at the bottom of the array, the identity key pair at the start of the
+    /// protected quarter, wherever that is on a part of `capacity` bytes.
+    pub const fn address(self, capacity: usize) -> u16 {
+        match self {
+            Slot::OwnAesKey => 0x00,
+            Slot::PeerAesKey => 0x0E,
+            Slot::PeerPublicKey => 0x1C,
+            Slot::OwnKeyPair => match IDENTITY_PROTECTION.start(capacity) {
+                Some(start) => start,
+                None => panic!("identity keys must be protectable"),
+            },
+        }
+    }
+
+    /// Length of the key itself, the record adds a header and CRC on top.
+    pub const fn len(self) -> usize {
+        match self {
+            Slot::OwnAesKey | Slot::PeerAesKey => 8,
+            Slot::PeerPublicKey => 16,
+            Slot::OwnKeyPair => 24,
+        }
+    }
+
+    /// Identity slots are written at provisioning time and live in the protected range.
+    pub const fn is_identity(self) -> bool {
+        matches!(self, Slot::OwnKeyPair)
+    }
+
+    /// Bit for this slot in a `WipeReport`.
+    pub const fn mask(self) -> u8 {
+        1 << self.index()
+    }
+
+    pub const fn record_len(self) -> usize {
+        HEADER_LEN + self.len() + CRC_LEN
+    }
+
+    /// Position in `ALL` order, which `WipeReport` and `WearReport` follow.
+    pub const fn index(self) -> u8 {
+        match self {
+            Slot::OwnAesKey => 0,
+            Slot::OwnKeyPair => 1,
+            Slot::PeerAesKey => 2,
+            Slot::PeerPublicKey => 3,
+        }
+    }
+
+    const fn from_index(index: u8) -> Option<Slot> {
+        match index {
+            0 => Some(Slot::OwnAesKey),
+            1 => Some(Slot::OwnKeyPair),
+            2 => Some(Slot::PeerAesKey),
+            3 => Some(Slot::PeerPublicKey),
+            _ => None,
+        }
+    }
+
+    const fn tag(self) -> u8 {
+        (FORMAT_VERSION << 4) | VALID | self.index()
+    }
+
+    const fn end(self, capacity: usize) -> usize {
+        self.address(capacity) as usize + self.record_len()
+    }
+}
+
+// Check the layout at compile time: every slot fits in the array and in the scratch buffer and is a
+// whole number of cipher blocks, no two slots overlap or run into the staging area, and only the
+// identity slots fall inside the block-protected range. The session area is the same on every part
+// and the protected quarter only moves up with size, so checking the AT25010B, the smallest part,
+// covers them all.
+const _: () = {
+    let capacity = Device::AT25010B.capacity;
+    let protected_start = match IDENTITY_PROTECTION.start(capacity) {
+        Some(start) => start,
+        None => panic!("identity keys must be protectable"),
+    };
+    let staging_end = STAGING_END;
+    assert!(staging_end <= protected_start as usize);
+    let mut i = 0;
+    while i < Slot::ALL.len() {
+        let slot = Slot::ALL[i];
+        let (address, end) = (slot.address(capacity) as usize, slot.end(capacity));
+        assert!(end <= capacity);
+        assert!(slot.len() <= MAX_KEY_LEN);
+        assert!(slot.len().is_multiple_of(AES_BLOCK_SIZE));
+        assert!(slot.index() < VALID);
+        assert!((address >= protected_start as usize) == slot.is_identity());
+        assert!(end <= STAGING_ADDRESS as usize || staging_end <= address);
+        let mut j = i + 1;
+        while j < Slot::ALL.len() {
+            let other = Slot::ALL[j];
+            assert!(end <= other.address(capacity) as usize || other.end(capacity) <= address);
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+/// A key type that can be stored in a slot.
+pub trait StoredKey: Zeroize + Sized {
+    const LEN: usize;
+    fn write_bytes(&self, buf: &mut [u8]);
+    fn read_bytes(buf: &[u8]) -> Self;
+}
+
+impl StoredKey for [u8; 8] {
+    const LEN: usize = 8;
+    fn write_bytes(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(self);
+    }
+    fn read_bytes(buf: &[u8]) -> Self {
+        buf.try_into().unwrap()
+    }
+}
+
+impl StoredKey for RSAPublicKey {
+    const LEN: usize = 16;
+    fn write_bytes(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.to_bytes());
+    }
+    fn read_bytes(buf: &[u8]) -> Self {
+        RSAPublicKey::from_bytes(buf.try_into().unwrap())
+    }
+}
+
+impl StoredKey for RSAKeyPair {
+    const LEN: usize = 24;
+    fn write_bytes(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.to_bytes());
+    }
+    fn read_bytes(buf: &[u8]) -> Self {
+        RSAKeyPair::from_bytes(buf.try_into().unwrap())
+    }
+}
+
+/// Ties a slot to the type stored in it.
+pub trait KeySlot {
+    const SLOT: Slot;
+    type Key: StoredKey;
+    // Evaluated when a slot is used, so a key type that does not match its slot fails to build.
+    const CHECK: () = assert!(Self::Key::LEN == Self::SLOT.len());
+}
+
+pub struct OwnAesKey;
+pub struct OwnKeyPair;
+pub struct PeerAesKey;
+pub struct PeerPublicKey;
+
+impl KeySlot for OwnAesKey {
+    const SLOT: Slot = Slot::OwnAesKey;
+    type Key = [u8; 8];
+}
+
+impl KeySlot for OwnKeyPair {
+    const SLOT: Slot = Slot::OwnKeyPair;
+    type Key = RSAKeyPair;
+}
+
+impl KeySlot for PeerAesKey {
+    const SLOT: Slot = Slot::PeerAesKey;
+    type Key = [u8; 8];
+}
+
+impl KeySlot for PeerPublicKey {
+    const SLOT: Slot = Slot::PeerPublicKey;
+    type Key = RSAPublicKey;
+}
+
+/// State of one copy of a record.
+#[derive(Clone, Copy)]
+enum Record {
+    /// Never written.
+    Blank,
+    /// Written, but fails the version, length or CRC check.
+    Torn,
+    /// A complete record. `live` is false for the tombstone left behind by `wipe`.
+    Complete { generation: u32, live: bool },
+}
+
+impl Record {
+    fn parse(slot: Slot, record: &[u8]) -> Record {
+        let tag = record[0];
+        if tag == 0xFF {
+            return Record::Blank;
+        }
+        let body_len = HEADER_LEN + slot.len();
+        if tag & !VALID != slot.tag() & !VALID
+            || record[1] as usize != slot.len()
+            || record[body_len] != crc8(&record[..body_len])
+        {
+            return Record::Torn;
+        }
+        Record::Complete {
+            generation: u32::from_le_bytes([record[2], record[3], record[4], 0]),
+            live: tag & VALID != 0,
+        }
+    }
+
+    fn generation(self) -> Option<u32> {
+        match self {
+            Record::Complete { generation, .. } => Some(generation),
+            _ => None,
+        }
+    }
+}
+
+/// A cached record and whether it came from the staging copy rather than the slot itself.
+#[derive(Clone, Copy)]
+struct Cached {
+    state: Record,
+    staged: bool,
+}
+
+pub struct KeyStore<S: Storage> {
+    storage: S,
+    cached: [Option<Cached>; SLOT_COUNT],
+    cache: Secret<[u8; CACHE_LEN]>, // one record per slot, indexed like `cached`
+    stats: CacheStats,
+    kek: Secret<[u8; 8]>,
+    capacity: usize,
+}
+
+impl<S: Storage> KeyStore<S> {
+    /// `kek` wraps every key on the chip, see `crypt::derive_kek`.
+    pub fn new(storage: S, kek: Secret<[u8; 8]>) -> KeyStore<S> {
+        let mut key_store = KeyStore {
+            cached: [None; SLOT_COUNT],
+            cache: Secret::new([0u8; CACHE_LEN]),
+            stats: CacheStats::default(),
+            capacity: storage.device().capacity,
+            storage,
+            kek,
+        };
+        // finish an update that was interrupted by a reset. This can only fail for the protected
+        // identity slot, in which case the staged copy still wins on every read.
+        key_store.recover().ok();
+
+        // load every slot now so the main loop never has to touch the SPI bus to read a key.
+        let mut record = Secret::new([0u8; MAX_RECORD_LEN]);
+        for slot in Slot::ALL {
+            key_store.current(slot, &mut record);
+        }
+        key_store
+    }
+
+    /// How much SPI traffic the cache has saved since boot.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stats.clone()
+    }
+
+    /// Read a key. Returns `Ok(None)` when the slot is blank or has been erased.
+    pub fn get<K: KeySlot>(&mut self, _slot: K) -> Result<Option<Secret<K::Key>>, Error> {
+        let () = K::CHECK;
+        let slot = K::SLOT;
+        let mut record = Secret::new([0u8; MAX_RECORD_LEN]);
+        match self.current(slot, &mut record) {
+            Record::Blank | Record::Complete { live: false, .. } => Ok(None),
+            Record::Torn => Err(Error::Corrupt(slot)),
+            Record::Complete { live: true, .. } => Ok(Some(Secret::new(K::Key::read_bytes(
+                &record[HEADER_LEN..HEADER_LEN + slot.len()],
+            )))),
+        }
+    }
+
+    pub fn put<K: KeySlot>(&mut self, _slot: K, key: &K::Key) -> Result<(), Error> {
+        let () = K::CHECK;
+        let mut payload = Secret::new([0u8; MAX_KEY_LEN]);
+        let payload = &mut payload[..K::SLOT.len()];
+        key.write_bytes(payload);
+        self.commit(K::SLOT, Some(payload))
+    }
+
+    /// Erase a slot and scrub every copy of the key from the array, then read both copies back to
+    /// make sure nothing is left.
+    pub fn wipe(&mut self, slot: Slot) -> Result<(), Error> {
+        // written even over a tombstone, so that staging holds this slot's record and not another's.
+        self.write(slot, None)?;
+        // a shorter record staged after a longer one leaves the tail of the longer key behind it.
+        let blank = [0xFF; MAX_RECORD_LEN];
+        self.storage.write_bytes(
+            STAGING_ADDRESS + slot.record_len() as u16,
+            &blank[slot.record_len()..],
+        )?;
+
+        let mut record = Secret::new([0u8; MAX_RECORD_LEN]);
+        self.storage
+            .read_bytes(self.address(slot), &mut record[..slot.record_len()]);
+        let home_clear = Self::is_tombstone(slot, &record[..]);
+        self.storage.read_bytes(STAGING_ADDRESS, &mut record[..]);
+        let staging_clear = Self::is_tombstone(slot, &record[..])
+            && record[slot.record_len()..].iter().all(|&byte| byte == 0xFF);
+
+        if home_clear && staging_clear {
+            Ok(())
+        } else {
+            Err(Error::WipeFailed(slot))
+        }
+    }
+
+    /// Wipe each slot in turn and collect the results for the link.
+    pub fn wipe_slots(&mut self, slots: &[Slot]) -> WipeReport {
+        let mut report = WipeReport::default();
+        for &slot in slots {
+            match self.wipe(slot) {
+                Ok(()) => report.wiped |= slot.mask(),
+                Err(_) => report.failed |= slot.mask(),
+            }
+        }
+        report
+    }
+
+    /// Wipe every slot, the identity key pair included. Block protection is lifted to do so, which
+    /// leaves the board unprovisioned until the next boot.
+    pub fn wipe_all(&mut self) -> WipeReport {
+        self.storage.set_block_protect(BlockProtect::None);
+        self.wipe_slots(&Slot::ALL)
+    }
+
+    fn is_tombstone(slot: Slot, record: &[u8]) -> bool {
+        matches!(
+            Record::parse(slot, record),
+            Record::Complete { live: false, .. }
+        ) && record[HEADER_LEN..HEADER_LEN + slot.len()]
+            .iter()
+            .all(|&byte| byte == 0xFF)
+    }
+
+    /// The underlying storage, for regions that live outside the key store such as the config
+    /// block. Writing to the slots or the staging area through this bypasses the cache.
+    pub fn storage(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Returns true once the identity key pair has been written and locked.
+    pub fn identity_locked(&mut self) -> bool {
+        self.storage.block_protect() == IDENTITY_PROTECTION
+    }
+
+    /// Write the identity key pair and lock it. This is meant to run once per board, afterwards
+    /// the pair can only be changed by clearing the block-protect bits first.
+    pub fn provision_identity(&mut self, key_pair: &RSAKeyPair) -> Result<(), Error> {
+        self.storage.set_block_protect(BlockProtect::None);
+        self.put(OwnKeyPair, key_pair)?;
+        self.storage.set_block_protect(IDENTITY_PROTECTION);
+        Ok(())
+    }
+
+    fn address(&self, slot: Slot) -> u16 {
+        slot.address(self.capacity)
+    }
+
+    /// Pick the newest complete copy of a slot and leave it in `record`, from the cache if we can.
+    fn current(&mut self, slot: Slot, record: &mut [u8; MAX_RECORD_LEN]) -> Record {
+        let index = slot.index() as usize;
+        let cache = &self.cache[index * MAX_RECORD_LEN..(index + 1) * MAX_RECORD_LEN];
+        if let Some(cached) = self.cached[index] {
+            record.copy_from_slice(cache);
+            self.stats.hits += 1;
+            // a miss reads at least the staging tag and the whole slot.
+            self.stats.bytes_saved += slot.record_len() as u32 + 1;
+            return cached.state;
+        }
+
+        self.stats.misses += 1;
+        let (state, staged) = self.load(slot, record);
+        self.fill_cache(slot, state, staged, record);
+        state
+    }
+
+    fn load(&mut self, slot: Slot, record: &mut [u8; MAX_RECORD_LEN]) -> (Record, bool) {
+        let mut staged = Secret::new([0u8; MAX_RECORD_LEN]);
+        let staged_state = self.read_staged(slot, &mut staged);
+
+        self.storage
+            .read_bytes(self.address(slot), &mut record[..slot.record_len()]);
+        self.unwrap(slot, record);
+        let home_state = Record::parse(slot, record);
+
+        match (home_state.generation(), staged_state.generation()) {
+            (Some(home), Some(staged_generation)) if staged_generation <= home => {
+                (home_state, false)
+            }
+            (_, Some(_)) => {
+                record.copy_from_slice(&staged[..]);
+                (staged_state, true)
+            }
+            _ => (home_state, false),
+        }
+    }
+
+    fn fill_cache(&mut self, slot: Slot, state: Record, staged: bool, record: &[u8]) {
+        let index = slot.index() as usize;
+        self.cache[index * MAX_RECORD_LEN..(index + 1) * MAX_RECORD_LEN]
+            .copy_from_slice(&record[..MAX_RECORD_LEN]);
+        self.cached[index] = Some(Cached { state, staged });
+    }
+
+    /// Drop this slot from the cache, along with any slot that was being served from the staging
+    /// copy, since that is about to be overwritten.
+    fn invalidate(&mut self, slot: Slot) {
+        for (index, cached) in self.cached.iter_mut().enumerate() {
+            let from_staging = matches!(cached, Some(Cached { staged: true, .. }));
+            if index == slot.index() as usize || from_staging {
+                *cached = None;
+            }
+        }
+    }
+
+    /// Read the staging copy if it belongs to `slot`, otherwise report it as blank.
+    fn read_staged(&mut self, slot: Slot, record: &mut [u8; MAX_RECORD_LEN]) -> Record {
+        self.storage.read_bytes(STAGING_ADDRESS, &mut record[..1]);
+        if record[0] == 0xFF || record[0] & 0b0000_0111 != slot.index() {
+            return Record::Blank;
+        }
+        self.storage
+            .read_bytes(STAGING_ADDRESS, &mut record[..slot.record_len()]);
+        self.unwrap(slot, record);
+        Record::parse(slot, record)
+    }
+
+    /// Encrypt the payload of a live record in place, just before it is written.
+    fn wrap(&self, slot: Slot, record: &mut [u8]) {
+        if record[0] & VALID != 0 {
+            let iv = Self::iv(record);
+            crypt::aes_wrap(
+                &self.kek,
+                &iv,
+                &mut record[HEADER_LEN..HEADER_LEN + slot.len()],
+            );
+        }
+    }
+
+    /// Decrypt the payload of a live record in place, just after it is read.
+    fn unwrap(&self, slot: Slot, record: &mut [u8]) {
+        if record[0] & VALID != 0 {
+            let iv = Self::iv(record);
+            crypt::aes_unwrap(
+                &self.kek,
+                &iv,
+                &mut record[HEADER_LEN..HEADER_LEN + slot.len()],
+            );
+        }
+    }
+
+    // The header holds the slot and generation, so every write of every slot gets its own IV.
+    fn iv(record: &[u8]) -> [u8; AES_BLOCK_SIZE] {
+        let mut iv = [0u8; AES_BLOCK_SIZE];
+        iv[..HEADER_LEN].copy_from_slice(&record[..HEADER_LEN]);
+        iv
+    }
+
+    fn commit(&mut self, slot: Slot, payload: Option<&[u8]>) -> Result<(), Error> {
+        let mut buf = Secret::new([0u8; MAX_RECORD_LEN]);
+        let current = self.current(slot, &mut buf);
+
+        // skip the write when the slot already holds exactly this, it would only cost a cycle.
+        if let Record::Complete { live, .. } = current {
+            let stored = &buf[HEADER_LEN..HEADER_LEN + slot.len()];
+            let unchanged = match payload {
+                Some(payload) => live && stored == payload,
+                None => !live,
+            };
+            if unchanged {
+                return Ok(());
+            }
+        }
+        self.write(slot, payload)
+    }
+
+    /// Write a new generation of the record through staging, whatever the slot holds now.
+    fn write(&mut self, slot: Slot, payload: Option<&[u8]>) -> Result<(), Error> {
+        let mut buf = Secret::new([0u8; MAX_RECORD_LEN]);
+        let current = self.current(slot, &mut buf);
+
+        // check before staging, a staged copy that can never reach its slot would shadow it.
+        if self
+            .storage
+            .block_protect()
+            .protects(self.capacity, self.address(slot))
+        {
+            return Err(Error::Eeprom(eeprom::Error::WriteProtected(
+                self.address(slot),
+            )));
+        }
+
+        let generation = match current.generation() {
+            Some(generation) => (generation + 1).min(MAX_GENERATION),
+            None => 0,
+        };
+
+        let body_len = HEADER_LEN + slot.len();
+        buf[slot.record_len()..].fill(0);
+        let record = &mut buf[..slot.record_len()];
+        record[0] = match payload {
+            Some(_) => slot.tag(),
+            None => slot.tag() & !VALID,
+        };
+        record[1] = slot.len() as u8;
+        record[2..HEADER_LEN].copy_from_slice(&generation.to_le_bytes()[..3]);
+        match payload {
+            Some(payload) => record[HEADER_LEN..body_len].copy_from_slice(payload),
+            None => record[HEADER_LEN..body_len].fill(0xFF),
+        }
+        record[body_len] = crc8(&record[..body_len]);
+
+        let mut wrapped = Secret::new([0u8; MAX_RECORD_LEN]);
+        let wrapped = &mut wrapped[..slot.record_len()];
+        wrapped.copy_from_slice(record);
+        self.wrap(slot, wrapped);
+
+        // only a write that went all the way through may refill the cache.
+        self.invalidate(slot);
+        // copy A. If power fails here the slot still holds the previous key.
+        self.storage.write_bytes(STAGING_ADDRESS, wrapped)?;
+        // copy B. If power fails here the staged copy is complete and newer, so reads use it.
+        self.storage.write_bytes(self.address(slot), wrapped)?;
+
+        let state = Record::Complete {
+            generation,
+            live: payload.is_some(),
+        };
+        self.fill_cache(slot, state, false, &buf[..]);
+        Ok(())
+    }
+
+    /// Copy a staged record that is newer than its slot back into place.
+    fn recover(&mut self) -> Result<(), Error> {
+        let mut tag = [0u8; 1];
+        self.storage.read_bytes(STAGING_ADDRESS, &mut tag);
+        let Some(slot) = Slot::from_index(tag[0] & 0b0000_0111) else {
+            return Ok(());
+        };
+
+        let mut staged = Secret::new([0u8; MAX_RECORD_LEN]);
+        let Some(staged_generation) = self.read_staged(slot, &mut staged).generation() else {
+            return Ok(());
+        };
+        let mut home = Secret::new([0u8; MAX_RECORD_LEN]);
+        self.storage
+            .read_bytes(self.address(slot), &mut home[..slot.record_len()]);
+        self.unwrap(slot, &mut home[..]);
+        match Record::parse(slot, &home[..]).generation() {
+            Some(generation) if generation >= staged_generation => Ok(()),
+            _ => {
+                self.wrap(slot, &mut staged[..]);
+                self.storage
+                    .write_bytes(self.address(slot), &staged[..slot.record_len()])?;
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use super::*;
+use crate::eeprom::sim::SimEeprom;
+
+const DEVICE_ID: [u8; 12] = *b"test-board-1";
+const OLD: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x08];
+const NEW: [u8; 8] = [0x81, 0x92, 0xA3, 0xB4, 0xC5, 0xD6, 0xE7, 0x78];
+const PEER: [u8; 8] = [0x0F; 8];
+
+fn open(sim: &mut SimEeprom) -> KeyStore<&mut SimEeprom> {
+    KeyStore::new(sim, crypt::derive_kek(&DEVICE_ID))
+}
+
+fn own_key(sim: &mut SimEeprom) -> Result<Option<[u8; 8]>, Error> {
+    open(sim).get(OwnAesKey).map(|key| key.map(|key| *key))
+}
+
+#[test]
+fn keys_read_back_after_a_reboot() {
+    let mut sim = SimEeprom::default();
+    let mut key_store = open(&mut sim);
+    let public = RSAPublicKey::new(0x1234_5678_9ABC, 0x10001);
+    key_store.put(OwnAesKey, &OLD).unwrap();
+    key_store.put(PeerAesKey, &PEER).unwrap();
+    key_store.put(PeerPublicKey, &public).unwrap();
+    key_store.put(OwnAesKey, &NEW).unwrap();
+
+    let mut key_store = open(&mut sim);
+    assert_eq!(*key_store.get(OwnAesKey).unwrap().unwrap(), NEW);
+    assert_eq!(*key_store.get(PeerAesKey).unwrap().unwrap(), PEER);
+    let stored = key_store.get(PeerPublicKey).unwrap().unwrap();
+    assert_eq!(stored.to_bytes(), public.to_bytes());
+}
+
+#[test]
+fn blank_and_wiped_slots_read_as_none() {
+    let mut sim = SimEeprom::default();
+    let mut key_store = open(&mut sim);
+    assert!(key_store.get(OwnAesKey).unwrap().is_none());
+    key_store.put(OwnAesKey, &OLD).unwrap();
+    key_store.wipe(Slot::OwnAesKey).unwrap();
+    assert!(key_store.get(OwnAesKey).unwrap().is_none());
+    assert!(own_key(&mut sim).unwrap().is_none());
+}
+
+#[test]
+fn a_power_cut_at_any_byte_leaves_the_old_key_or_the_new_one() {
+    // replacing a key writes its record twice, once to staging and once over the slot.
+    let record_len = Slot::OwnAesKey.record_len();
+    for cut_after in 0..=2 * record_len {
+        let mut sim = SimEeprom::default();
+        let mut key_store = open(&mut sim);
+        key_store.put(OwnAesKey, &OLD).unwrap();
+        key_store.put(PeerAesKey, &PEER).unwrap();
+
+        sim.cut_power_after(cut_after);
+        open(&mut sim).put(OwnAesKey, &NEW).unwrap();
+        sim.restore_power();
+
+        let mut key_store = open(&mut sim);
+        let key = *key_store.get(OwnAesKey).unwrap().unwrap();
+        match cut_after {
+            // the staging copy was not complete yet.
+            n if n < record_len => assert_eq!(key, OLD, "cut after {n} bytes"),
+            n => assert_eq!(key, NEW, "cut after {n} bytes"),
+        }
+        assert_eq!(*key_store.get(PeerAesKey).unwrap().unwrap(), PEER);
+
+        // the store takes updates again after the reboot.
+        key_store.put(OwnAesKey, &PEER).unwrap();
+        assert_eq!(own_key(&mut sim).unwrap(), Some(PEER));
+    }
+}
+
+#[test]
+fn a_staged_update_is_finished_at_boot() {
+    let mut sim = SimEeprom::default();
+    open(&mut sim).put(OwnAesKey, &OLD).unwrap();
+
+    // power fails right after the staging copy went in.
+    sim.cut_power_after(Slot::OwnAesKey.record_len());
+    open(&mut sim).put(OwnAesKey, &NEW).unwrap();
+    sim.restore_power();
+    let written = sim.bytes_written();
+
+    let home = Slot::OwnAesKey.address(sim.device().capacity) as usize;
+    let torn = sim.memory()[home..home + Slot::OwnAesKey.record_len()].to_vec();
+    assert_eq!(own_key(&mut sim).unwrap(), Some(NEW));
+    // the boot copied the staged record over the slot.
+    assert!(sim.bytes_written() > written);
+    assert_ne!(
+        sim.memory()[home..home + Slot::OwnAesKey.record_len()],
+        torn[..]
+    );
+}
+
+#[test]
+fn wiping_twice_reports_every_slot_wiped() {
+    let mut sim = SimEeprom::default();
+    let mut key_store = open(&mut sim);
+    key_store.put(OwnAesKey, &OLD).unwrap();
+    key_store.put(PeerAesKey, &PEER).unwrap();
+
+    for _ in 0..2 {
+        let report = key_store.wipe_all();
+        assert_eq!(report.wiped, 0b1111);
+        assert_eq!(report.failed, 0);
+    }
+    assert!(key_store.get(OwnAesKey).unwrap().is_none());
+    assert!(key_store.get(PeerAesKey).unwrap().is_none());
+}
+
+#[test]
+fn keys_from_another_board_read_as_corrupt() {
+    let mut sim = SimEeprom::default();
+    open(&mut sim).put(OwnAesKey, &OLD).unwrap();
+    open(&mut sim).put(PeerAesKey, &PEER).unwrap();
+    open(&mut sim).wipe(Slot::PeerAesKey).unwrap();
+    // the key never reaches the chip in the clear.
+    assert!(!sim.memory().windows(OLD.len()).any(|bytes| bytes == OLD));
+
+    let mut other = KeyStore::new(&mut sim, crypt::derive_kek(b"test-board-2"));
+    assert_eq!(
+        other.get(OwnAesKey).map(|key| key.map(|key| *key)),
+        Err(Error::Corrupt(Slot::OwnAesKey))
+    );
+    // tombstones carry nothing to wrap and read the same everywhere.
+    assert!(other.get(PeerAesKey).unwrap().is_none());
+    assert_eq!(own_key(&mut sim).unwrap(), Some(OLD));
+}
@@ -0,0 +1,114 @@
+//! Link start and protocol version negotiation
+//!
+//! Postcard encodes enum variants by index, so a board on an older protocol misreads anything
+//! added after its time. Each node announces itself with a `Hello` at boot, and the receiver
+//! decides what to do with the peer:
+//!
+//! - same version: carry on.
+//! - peer older, but no older than `MIN_PROTOCOL_VERSION`: downgrade, nothing newer than the
+//!   peer's version is sent to it, see `Data::since`.
+//! - peer newer: carry on at our version, the peer applies the same policy and downgrades to us.
+//! - peer older than `MIN_PROTOCOL_VERSION`: reject, answer with `Status::Incompatible` and drop
+//!   everything else it sends until it comes back with a version we speak.
+//!
+//! A node resends its `Hello` until it has heard one back, and answers every `Hello` unless its
+//! own went out just before, so two nodes that boot together do not keep answering each other.
+
+use crate::messages::{Capabilities, Data, Hello, Status, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// How often to resend our `Hello` until the peer's arrives.
+pub const RETRY_MS: u32 = 1_000;
+/// A `Hello` that arrives this soon after ours went out already answers it.
+pub const HOLDOFF_MS: u32 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Compatible,
+    /// The peer is older, talk to it at its version.
+    Downgraded(u16),
+    Rejected,
+}
+
+pub struct Link {
+    ours: Hello,
+    /// What we made of the peer's last `Hello`, if there was one.
+    peer: Option<Verdict>,
+    last_sent: Option<u32>,
+}
+
+/// What to send in answer to a `Hello`.
+pub struct Reply {
+    pub hello: Option<Hello>,
+    pub status: Option<Status>,
+}
+
+impl Link {
+    pub fn new(node_id: u32, capabilities: Capabilities) -> Link {
+        Link {
+            ours: Hello {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities,
+                node_id,
+            },
+            peer: None,
+            last_sent: None,
+        }
+    }
+
+    /// The version to talk to the peer at, ours until we know better.
+    pub fn version(&self) -> u16 {
+        match self.peer {
+            Some(Verdict::Downgraded(version)) => version,
+            _ => PROTOCOL_VERSION,
+        }
+    }
+
+    /// Our `Hello`, if it is time to send it.
+    pub fn poll(&mut self, now: u32) -> Option<Hello> {
+        let due = match (&self.peer, self.last_sent) {
+            (Some(_), _) => false,
+            (None, Some(last)) => now.wrapping_sub(last) >= RETRY_MS,
+            (None, None) => true,
+        };
+        due.then(|| self.sent(now))
+    }
+
+    pub fn on_hello(&mut self, now: u32, theirs: Hello) -> Reply {
+        let verdict = negotiate(theirs.protocol_version);
+        let answered = self
+            .last_sent
+            .is_some_and(|last| now.wrapping_sub(last) < HOLDOFF_MS);
+        self.peer = Some(verdict);
+        Reply {
+            hello: (!answered).then(|| self.sent(now)),
+            status: (verdict == Verdict::Rejected).then_some(Status::Incompatible {
+                ours: PROTOCOL_VERSION,
+                oldest: MIN_PROTOCOL_VERSION,
+            }),
+        }
+    }
+
+    /// Whether to act on a message from the peer. A rejected peer only gets to say `Hello` again.
+    pub fn accepts(&self, data: &Data) -> bool {
+        match self.peer {
+            Some(Verdict::Rejected) => matches!(data, Data::Hello(_)),
+            _ => true,
+        }
+    }
+
+    fn sent(&mut self, now: u32) -> Hello {
+        self.last_sent = Some(now);
+        self.ours.clone()
+    }
+}
+
+/// The compatibility policy, from the peer's protocol version.
+pub fn negotiate(theirs: u16) -> Verdict {
+    if theirs < MIN_PROTOCOL_VERSION {
+        Verdict::Rejected
+    } else if theirs < PROTOCOL_VERSION {
+        Verdict::Downgraded(theirs)
+    } else {
+        Verdict::Compatible
+    }
+}
@@ -1,11 +1,27 @@
 #![no_std]
 #![no_main]
 
+mod clock;
+mod commands;
 mod coms_manager;
+mod config;
 mod crypt;
 mod eeprom;
+mod envelope;
+mod event_log;
+mod filter;
+mod fragment;
+mod key_store;
+mod link;
 mod messages;
 mod mux;
+mod node;
+mod rpc;
+mod session;
+mod telemetry;
+mod temperature;
+mod wear;
+mod wire;
 
 use core::cell::RefCell;
 use core::ops::DerefMut;
@@ -13,6 +29,7 @@ use cortex_m::interrupt::Mutex;
 use cortex_m_rt::entry;
 
 use hal::{pac, pac::interrupt, prelude::*, pwm, serial::Serial};
+use key_store::{OwnAesKey, OwnKeyPair, PeerAesKey, PeerPublicKey};
 use messages::Temperature;
 use stm32f0xx_hal::{self as hal, adc::Adc};
 
@@ -20,6 +37,21 @@ use stm32f0xx_hal::{self as hal, adc::Adc};
 // What is a RefCell? It is a mutable memory location with dynamically checked borrow rules.
 static COMS: Mutex<RefCell<Option<coms_manager::ComsManager>>> = Mutex::new(RefCell::new(None));
 
+/// RAM on the STM32F030F4, see `memory.x`.
+const RAM: usize = 4 * 1024;
+/// Left for the stack: the interrupt frames, sealing a message and encoding it into fragments.
+const STACK_RESERVE: usize = 2 * 1024;
+
+// `COMS` and the `Node` that `main` never returns from hold nearly all the state, the rest of the
+// RAM is stack. Sizes are taken on whatever target this is built for, a 64-bit host only makes
+// them larger.
+const _: () = assert!(
+    core::mem::size_of::<Mutex<RefCell<Option<coms_manager::ComsManager>>>>()
+        + core::mem::size_of::<node::Node>()
+        + STACK_RESERVE
+        <= RAM
+);
+
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
     loop {}
@@ -39,6 +71,10 @@ fn main() -> ! {
     if let Some(mut dp) = pac::Peripherals::take() {
         let mut rcc = dp.RCC.configure().sysclk(8.mhz()).freeze(&mut dp.FLASH);
 
+        if let Some(cp) = cortex_m::Peripherals::take() {
+            clock::start(cp.SYST, rcc.clocks.sysclk().0);
+        }
+
         let gpioa = dp.GPIOA.split(&mut rcc);
 
         let adc = Adc::new(dp.ADC, &mut rcc);
@@ -47,51 +83,75 @@ fn main() -> ! {
             mux::Mux::new(
                 gpioa.pa1.into_push_pull_output(cs),
                 gpioa.pa0.into_push_pull_output(cs),
-                gpioa.pa2.into_push_pull_output(cs),
-                adc,
+                mux::AdcIo::new(gpioa.pa2.into_push_pull_output(cs), adc),
             )
         });
 
         mux.execute(mux::Channel::RedLED);
 
-        let mut eeprom_manager = cortex_m::interrupt::free(|cs| {
+        let eeprom_manager = cortex_m::interrupt::free(|cs| {
             eeprom::EepromManager::new(
                 gpioa.pa6.into_alternate_af0(cs),
                 gpioa.pa7.into_alternate_af0(cs),
                 gpioa.pa5.into_alternate_af0(cs),
                 &mut rcc,
                 dp.SPI1,
+                eeprom::Device::AT25010B,
             )
         });
 
-        // create a scope to free the memory used by the keys.
-        {
-            // generate an RSA key pair. Never do this in production code.
-            let pub_key = crypt::RSAPublicKey::new(0x10001, 0x10001);
-            let priv_key = crypt::RSAPrivateKey::new(0x10001, 0x12345);
+        // keys are wrapped with a key derived from this MCU, so the EEPROM is useless on its own.
+        let uid = device_id();
+        let kek = crypt::derive_kek(&uid);
+        // every write is counted, on parts with room for the counts.
+        let mut key_store = key_store::KeyStore::new(wear::Counted::new(eeprom_manager), kek);
+
+        // a blank or corrupt config block gives the defaults.
+        let config = config::Config::load(key_store.storage());
 
-            // store the private key in the EEPROM
-            let priv_key_bytes = priv_key.to_bytes();
+        let sensor = temperature::TemperatureSensor::new(
+            temperature::Calibration::load(key_store.storage()),
+            config.sampling,
+        );
 
-            eeprom_manager.write_16_byte_key(priv_key_bytes, eeprom::RSA_PRIV_KEY_ADDRESS);
+        let telemetry = telemetry::Scheduler::new(telemetry::period(&config));
 
-            // store the public key in the EEPROM
-            let pub_key_bytes = pub_key.to_bytes();
+        // the smallest parts have no room for the log, the node then runs without one.
+        let mut event_log = event_log::EventLog::new(key_store.storage()).ok();
+        if let Some(event_log) = &mut event_log {
+            event_log
+                .append(key_store.storage(), clock::millis(), &messages::Event::Boot)
+                .ok();
+        }
 
-            eeprom_manager.write_16_byte_key(pub_key_bytes, eeprom::RSA_PUB_KEY_ADDRESS);
+        let counts_wear = key_store.storage().report().is_some();
+        let capabilities = capabilities(&config, event_log.is_some(), counts_wear);
+        let mut link = link::Link::new(node_id(&uid), capabilities);
 
-            // generate the AES key
-            let seed = [0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8];
-            let aes_key = crypt::generate_aes_key(&seed);
+        // create a scope to free the memory used by the keys.
+        {
+            // The identity keys are written once and then locked with the block-protect bits, so
+            // only an unprovisioned board stores them. A locked pair that does not unwrap came from
+            // another board or an older format, and one that is not ours predates the fixed toy
+            // pair, so we provision over both.
+            let provisioned = crypt::PROVISIONED.public();
+            let current = matches!(
+                key_store.get(OwnKeyPair),
+                Ok(Some(key_pair)) if key_pair.public() == provisioned
+            );
+            if !key_store.identity_locked() || !current {
+                // a fixed, publicly known key pair. Never do this in production code.
+                key_store.provision_identity(&crypt::PROVISIONED).unwrap();
+            }
 
-            // store the AES key in the EEPROM
-            eeprom_manager.write_8_byte_key(aes_key, eeprom::AES_KEY_ADDRESS);
+            // store the AES key in the EEPROM, this is skipped when the stored key is unchanged.
+            key_store.put(OwnAesKey, &generate_aes_key()).unwrap();
         }
 
         let tx = cortex_m::interrupt::free(move |cs| gpioa.pa9.into_alternate_af1(cs));
         let rx = cortex_m::interrupt::free(move |cs| gpioa.pa10.into_alternate_af1(cs));
 
-        let serial = Serial::usart1(dp.USART1, (tx, rx), 115_200.bps(), &mut rcc);
+        let serial = Serial::usart1(dp.USART1, (tx, rx), config.baud_rate.bps(), &mut rcc);
 
         let (tx, rx) = serial.split();
 
@@ -103,7 +163,7 @@ fn main() -> ! {
 
         let channel = cortex_m::interrupt::free(move |cs| gpioa.pa4.into_alternate_af4(cs));
 
-        let pwm = pwm::tim14(dp.TIM14, channel, &mut rcc, 20u32.khz());
+        let pwm = pwm::tim14(dp.TIM14, channel, &mut rcc, config.pwm_khz.khz());
         let mut ch1 = pwm;
         let max_duty = ch1.get_max_duty();
         ch1.set_duty(max_duty / 2);
@@ -112,143 +172,244 @@ fn main() -> ! {
         // init complete
         mux.execute(mux::Channel::GreenLED);
 
+        // report how worn the EEPROM is, bench boards get cycled a lot.
+        if let Some(report) = key_store.storage().report() {
+            node::send(&messages::Data::Wear(report));
+        }
+
+        let mut node = node::Node {
+            key_store,
+            config,
+            sensor,
+            mux,
+            event_log,
+            telemetry,
+            session: session::Session::new(),
+            after_response: session::Action::Nothing,
+            over_temperature: false,
+        };
+
+        // pair with the other node, unless the keys it left in the EEPROM still match ours.
+        {
+            let key_store = &mut node.key_store;
+            let has_peer_public_key = matches!(key_store.get(PeerPublicKey), Ok(Some(_)));
+            let keys_match = matches!(
+                (key_store.get(OwnAesKey), key_store.get(PeerAesKey)),
+                (Ok(Some(own)), Ok(Some(peer))) if *own == *peer
+            );
+            let action = node
+                .session
+                .start(clock::millis(), has_peer_public_key, keys_match);
+            node.perform(action);
+        }
+
         // we can now enter the main loop and start brodcasting
         loop {
-            let msg = cortex_m::interrupt::free(|cs| {
-                if let Some(ref mut coms_manager) = COMS.borrow(cs).borrow_mut().deref_mut() {
-                    if coms_manager.has_new_message() {
-                        coms_manager.receive()
-                    } else {
-                        None
-                    }
+            let msg = node::coms(|coms_manager| {
+                if coms_manager.has_new_message() {
+                    coms_manager.receive()
                 } else {
                     None
                 }
-            });
-            if let Some(msg) = msg {
-                cortex_m::interrupt::free(|cs| {
-                    match msg.data {
-                        messages::Data::Command(cmd) => match cmd {
-                            messages::Command::DeleteAESKey => {
-                                eeprom_manager.write_8_byte_key(
-                                    [0, 0, 0, 0, 0, 0, 0, 0],
-                                    eeprom::AES_KEY_ADDRESS,
-                                );
-                            }
-                        },
-                        messages::Data::RSAPublicKey(key) => {
-                            // take the key and write it to memory.
-                            let key_bytes = key.to_bytes();
-                            for i in 0..16 {
-                                eeprom_manager.write_memory(i + 48, key_bytes[i as usize]);
-                            }
-                        }
-                        messages::Data::Status(status) => match status {
-                            messages::Status::UnkownAESKey => {
-                                // Send out our AES key.
-                                if let Some(ref mut coms_manager) =
-                                    COMS.borrow(cs).borrow_mut().deref_mut()
-                                {
-                                    let key =
-                                        eeprom_manager.read_8_byte_key(eeprom::AES_KEY_ADDRESS);
-                                    let msg = messages::Data::AESKey(key);
-                                    coms_manager.send(&msg);
-                                }
-                            }
-                            messages::Status::UnkownPublicKey => {
-                                // Send out our public key.
-                                if let Some(ref mut coms_manager) =
-                                    COMS.borrow(cs).borrow_mut().deref_mut()
-                                {
-                                    let mut key_bytes = [0; 16];
-                                    for i in 0..16 {
-                                        // casting here cannot panic because a u8 will always fit into a usize.
-                                        key_bytes[i as usize] = eeprom_manager.read_memory(i + 16);
-                                    }
-                                    // create our message packet to send.
-                                    let msg = messages::Data::RSAPublicKey(
-                                        crypt::RSAPublicKey::from_bytes(&key_bytes),
-                                    );
-                                    coms_manager.send(&msg);
-                                }
-                            }
-                        },
-                        messages::Data::Temperature(data) => {
-                            // get the AES key from memory
-                            let mut aes_key: [u8; 8] = [0; 8];
-                            for i in 0..8 {
-                                aes_key[i] = eeprom_manager.read_memory(i as u8 + 32);
-                            }
-
-                            // unstuff the message
-                            let unstuffed = coms_manager::ComsManager::unstuff_message(&data);
-
-                            // decrypt the message
-                            let mut decrypted = crypt::aes_decrypt(&aes_key, &unstuffed);
-
-                            // deserialize the message
-                            if let Ok(msg) =
-                                postcard::from_bytes_cobs::<Temperature>(&mut decrypted.as_mut())
-                            {
-                                // check if the temperature is too high
-                                if msg.temp > 100.0 {
-                                    // turn on the red LED
-                                    mux.execute(mux::Channel::RedLED);
-                                } else {
-                                    // turn on the green LED
-                                    mux.execute(mux::Channel::GreenLED);
-                                }
-                            } else {
-                                // we cannot decrypt the message, delete the AES key and send a message to the other device to delete the AES key.
-                                for i in 0..16 {
-                                    eeprom_manager.write_memory(i + 32, 0);
-                                }
-                                if let Some(ref mut coms_manager) =
-                                    COMS.borrow(cs).borrow_mut().deref_mut()
-                                {
-                                    let msg =
-                                        messages::Data::Command(messages::Command::DeleteAESKey);
-                                    coms_manager.send(&msg);
-                                }
-                            }
-                        }
-                        messages::Data::AESKey(key) => {
-                            // use our private key to decrypt the AES key
-                            // get the key from eeprom
-                            let key_bytes =
-                                eeprom_manager.read_16_byte_key(eeprom::RSA_PRIV_KEY_ADDRESS);
-
-                            // recreat the private key structure
-                            let priv_key = crypt::RSAPrivateKey::from_bytes(&key_bytes);
-
-                            // decrypt the AES key
-                            let decrypted = crypt::decrypt(&priv_key, &key);
-
-                            eeprom_manager
-                                .write_8_byte_key(decrypted, eeprom::FORIEGN_AES_KEY_ADDRESS);
-                        }
-                    }
-                })
+            })
+            .flatten();
+            if let Some(msg) = msg.filter(|msg| link.accepts(&msg.data)) {
+                handle(&mut node, &mut link, msg);
             }
 
-            // try and send a temperature message
-            let aes_key = eeprom_manager.read_8_byte_key(eeprom::AES_KEY_ADDRESS);
+            // our own key was deleted or is torn, make a new one before pairing again.
+            if !matches!(node.key_store.get(OwnAesKey), Ok(Some(_))) {
+                node.key_store.put(OwnAesKey, &generate_aes_key()).unwrap();
+            }
 
-            let foriegn_aes_key = eeprom_manager.read_8_byte_key(eeprom::FORIEGN_AES_KEY_ADDRESS);
+            // announce ourselves until the peer has answered.
+            if let Some(hello) = link.poll(clock::millis()) {
+                node::send(&messages::Data::Hello(hello));
+            }
 
-            if aes_key == foriegn_aes_key {
-                // we have the same AES key, we can send the message
-            } else {
-                // we do not have the same AES key, we need to request the AES key from the other device.
-                cortex_m::interrupt::free(|cs| {
-                    if let Some(ref mut coms_manager) = COMS.borrow(cs).borrow_mut().deref_mut() {
-                        let msg = messages::Data::Status(messages::Status::UnkownAESKey);
-                        coms_manager.send(&msg);
-                    }
-                });
+            // resend whatever the key exchange is waiting on, or give up on it for a while.
+            let action = node.session.poll(clock::millis());
+            node.perform(action);
+
+            if node.session.is_established()
+                && matches!(node.config.role, config::Role::Sensor { .. })
+                && node.telemetry.due(clock::millis())
+            {
+                if let Ok(temp) = node.sensor.read_celsius(&mut node.mux) {
+                    node::send(&messages::Data::Temperature(Temperature { temp }));
+                }
             }
         }
     }
 
     panic!()
 }
+
+/// Act on one message from the peer. Once there is a session key, only the link setup gets here
+/// without having been sealed, the receiver turns down anything else sent in the clear.
+fn handle(node: &mut node::Node, link: &mut link::Link, msg: messages::Message) {
+    match msg.data {
+        messages::Data::Hello(hello) => {
+            let reply = link.on_hello(clock::millis(), hello);
+            node::coms(|coms_manager| coms_manager.set_peer_version(link.version()));
+            if let Some(status) = reply.status {
+                node::send(&messages::Data::Status(status));
+            }
+            if let Some(hello) = reply.hello {
+                node::send(&messages::Data::Hello(hello));
+            }
+        }
+        messages::Data::Command(command) => {
+            let response = commands::REGISTRY.dispatch(node, msg.id, command);
+            node::send(&response.into_data(link.version()));
+            // only now drop the session key, the peer could not have opened the response otherwise.
+            let action = core::mem::replace(&mut node.after_response, session::Action::Nothing);
+            node.perform(action);
+        }
+        messages::Data::RSAPublicKey(key) => {
+            // take the key and write it to memory.
+            if node.key_store.put(PeerPublicKey, &key).is_err() {
+                node::report(messages::ErrorCode::Eeprom, msg.id);
+                return;
+            }
+            let action = node.session.on_public_key(clock::millis());
+            node.perform(action);
+        }
+        messages::Data::Status(status) => match status {
+            messages::Status::UnkownAESKey => {
+                let action = node.session.on_session_key_requested();
+                node.perform(action);
+            }
+            messages::Status::UnkownPublicKey => {
+                let action = node.session.on_public_key_requested();
+                node.perform(action);
+            }
+            messages::Status::KeysWiped(_)
+            | messages::Status::LogDumped(_)
+            | messages::Status::SensorFailed
+            | messages::Status::Incompatible { .. } => {}
+            // the peer cannot read what we seal, go get a key that works.
+            messages::Status::Error(messages::ErrorReport {
+                code: messages::ErrorCode::DecryptFailed | messages::ErrorCode::AuthFailed,
+                ..
+            }) => {
+                let action = node.session.on_session_lost(clock::millis());
+                node.perform(action);
+            }
+            messages::Status::Error(_) => {}
+        },
+        messages::Data::Temperature(msg) => {
+            // check if the temperature is too high
+            if msg.temp > node.config.threshold_celsius() {
+                // turn on the red LED
+                node.mux.execute(mux::Channel::RedLED);
+                if !node.over_temperature {
+                    node.log(&messages::Event::OverTemperature(msg.temp));
+                }
+                node.over_temperature = true;
+            } else {
+                // turn on the green LED
+                node.mux.execute(mux::Channel::GreenLED);
+                node.over_temperature = false;
+            }
+        }
+        // the link hands sealed messages on only when they did not open. With a session key that
+        // means the seal checked out but what it holds did not decode, the link drops anything
+        // that fails the tag check.
+        messages::Data::Encrypted(_) => {
+            // we cannot read the message until the key exchange has happened, the session will
+            // request the key.
+            if !node.session.is_established() {
+                return;
+            }
+            // the peer holds our key but we do not understand each other. Drop its AES key and
+            // tell it why, still sealed so it knows the report is ours, then rekey.
+            node.key_store.wipe(key_store::Slot::PeerAesKey).ok();
+            node.log(&messages::Event::DecryptFailed);
+            node::report(messages::ErrorCode::DecryptFailed, msg.id);
+            let action = node.session.on_session_lost(clock::millis());
+            node.perform(action);
+        }
+        messages::Data::Wear(_)
+        | messages::Data::CacheStats(_)
+        | messages::Data::Config(_)
+        | messages::Data::Log(_)
+        | messages::Data::Calibration(_)
+        | messages::Data::Reading(_)
+        | messages::Data::Response(_)
+        | messages::Data::Fragment(_) => {}
+        messages::Data::AESKey(key) => {
+            // use our private key to decrypt the AES key
+            // get the key from eeprom
+            let key_pair = match node.key_store.get(OwnKeyPair) {
+                Ok(Some(key_pair)) => key_pair,
+                Ok(None) => return,
+                Err(_) => {
+                    node::report(messages::ErrorCode::KeyCorrupt, msg.id);
+                    return;
+                }
+            };
+            let priv_key = crypt::Secret::new(key_pair.private());
+
+            // decrypt the AES key
+            let decrypted = crypt::Secret::new(crypt::decrypt(&priv_key, &key));
+
+            if node.key_store.put(PeerAesKey, &decrypted).is_err() {
+                node::report(messages::ErrorCode::Eeprom, msg.id);
+                return;
+            }
+
+            let Ok(Some(own)) = node.key_store.get(OwnAesKey) else {
+                return;
+            };
+            let action = node
+                .session
+                .on_session_key(clock::millis(), own.cmp(&decrypted));
+            node.perform(action);
+        }
+    }
+}
+
+/// The 96 bit unique ID the factory burns into every STM32F0.
+fn device_id() -> [u8; 12] {
+    const UID_BASE: *const u8 = 0x1FFF_F7AC as *const u8;
+    let mut id = [0u8; 12];
+    for (i, byte) in id.iter_mut().enumerate() {
+        // SAFETY: the UID is 12 bytes of read-only system memory, present on every part.
+        *byte = unsafe { core::ptr::read_volatile(UID_BASE.add(i)) };
+    }
+    id
+}
+
+/// The unique ID folded into the `node_id` of our `Hello`.
+fn node_id(uid: &[u8; 12]) -> u32 {
+    uid.as_chunks::<4>()
+        .0
+        .iter()
+        .fold(0, |id, word| id ^ u32::from_le_bytes(*word))
+}
+
+/// What we announce in our `Hello`. The role is the one we booted with.
+fn capabilities(
+    config: &config::Config,
+    has_log: bool,
+    counts_wear: bool,
+) -> messages::Capabilities {
+    let mut capabilities = messages::Capabilities::CALIBRATION;
+    if has_log {
+        capabilities = capabilities.union(messages::Capabilities::EVENT_LOG);
+    }
+    if counts_wear {
+        capabilities = capabilities.union(messages::Capabilities::WEAR);
+    }
+    if matches!(config.role, config::Role::Sensor { .. }) {
+        capabilities = capabilities.union(messages::Capabilities::TELEMETRY);
+    }
+    capabilities
+}
+
+fn generate_aes_key() -> crypt::Secret<[u8; 8]> {
+    // generate the AES key
+    let seed = [0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8];
+    crypt::Secret::new(crypt::generate_aes_key(&seed))
+}
@@ -1,6 +1,23 @@
 //! Message definitions
 
+use core::marker::PhantomData;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::Config;
 use crate::crypt::RSAPublicKey;
+use crate::envelope::{self, EncryptedPayload};
+use crate::fragment::Fragment;
+use crate::temperature::Calibration;
+
+#[cfg(test)]
+mod tests;
+
+/// Bump whenever `Data` or anything in it changes encoding, and record the new version in
+/// `Data::since` for the variants that came with it.
+pub const PROTOCOL_VERSION: u16 = 5;
+/// The oldest version this firmware still talks to, see `link`.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Message {
@@ -14,7 +31,121 @@ pub enum Data {
     Status(Status),
     Command(Command),
     AESKey([u8; 8]),
-    Temperature([u8; 32]),
+    Temperature(Temperature),
+    Wear(WearReport),
+    CacheStats(CacheStats),
+    Config(Config),
+    Log(LogEntry),
+    Calibration(Calibration),
+    Reading(Reading),
+    /// Any other variant, sealed by `ComsManager` once the nodes share a session key.
+    Encrypted(Encrypted<Data>),
+    /// Peers of every version have to read this, so neither it nor anything before it may change.
+    Hello(Hello),
+    /// Answers a `Command`.
+    Response(Response),
+    /// Part of a message too large for one frame, `ComsManager` puts it back together.
+    Fragment(Fragment),
+}
+
+impl Data {
+    /// Messages that set up the link and the session key, these always travel in the clear. So do
+    /// fragments, what they carry was sealed before it was split. A report of a seal that did not
+    /// open is not one of them: it is only sent for a message that passed the tag check, so the
+    /// peer still holds the key that opens the report.
+    pub fn is_link_setup(&self) -> bool {
+        matches!(
+            self,
+            Data::RSAPublicKey(_)
+                | Data::AESKey(_)
+                | Data::Status(
+                    Status::UnkownPublicKey | Status::UnkownAESKey | Status::Incompatible { .. }
+                )
+                | Data::Encrypted(_)
+                | Data::Hello(_)
+                | Data::Fragment(_)
+        )
+    }
+
+    /// The protocol version that introduced this variant, a peer on an older one cannot decode it.
+    pub fn since(&self) -> u16 {
+        match self {
+            // came in with version 2, a version 1 peer has no such variant.
+            Data::Status(Status::Error(report)) => report.code.since().max(2),
+            Data::Response(Response {
+                result: Err(code), ..
+            }) => code.since().max(2),
+            Data::Wear(_)
+            | Data::Response(Response {
+                result: Ok(Reply::Wear(_)),
+                ..
+            }) => 5,
+            Data::RSAPublicKey(_)
+            | Data::Status(_)
+            | Data::Command(_)
+            | Data::AESKey(_)
+            | Data::Temperature(_)
+            | Data::CacheStats(_)
+            | Data::Config(_)
+            | Data::Log(_)
+            | Data::Calibration(_)
+            | Data::Reading(_)
+            | Data::Encrypted(_)
+            | Data::Hello(_) => 1,
+            Data::Response(_) => 2,
+            Data::Fragment(_) => 3,
+        }
+    }
+}
+
+/// Sent by each node at link start, see `link`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+    /// Folded from the MCU's unique ID, stays the same across resets.
+    pub node_id: u32,
+}
+
+/// What a node can do beyond the base protocol, one bit each.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(pub u16);
+
+impl Capabilities {
+    /// Runs as a sensor and broadcasts `Temperature`.
+    pub const TELEMETRY: Capabilities = Capabilities(1 << 0);
+    /// Keeps an event log that `DumpLog` reads back.
+    pub const EVENT_LOG: Capabilities = Capabilities(1 << 1);
+    /// Has room to store a sensor calibration.
+    pub const CALIBRATION: Capabilities = Capabilities(1 << 2);
+    /// Counts EEPROM writes, `ReportWear` reads them back.
+    pub const WEAR: Capabilities = Capabilities(1 << 3);
+
+    pub const fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+/// A `T` encrypted under the session key, see `envelope` for the layout.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(bound = "")]
+pub struct Encrypted<T> {
+    payload: EncryptedPayload,
+    #[serde(skip)]
+    kind: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Encrypted<T> {
+    pub fn seal(key: &[u8; 8], nonce: u32, value: &T) -> Result<Self, envelope::Error> {
+        Ok(Encrypted {
+            payload: EncryptedPayload::seal(key, nonce, value)?,
+            kind: PhantomData,
+        })
+    }
+
+    pub fn open(&self, key: &[u8; 8]) -> Result<T, envelope::Error> {
+        self.payload.open(key)
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
@@ -27,9 +158,275 @@ pub enum Status {
     UnkownPublicKey,
     // We cannot find the AES key in the EEPROM
     UnkownAESKey,
+    /// The answers to `DeleteAESKey`, `WipeAllKeys`, `DumpLog` and `ReadTemperature` before
+    /// version 2, which sends a `Response` instead.
+    KeysWiped(WipeReport),
+    LogDumped(u32),
+    SensorFailed,
+    /// Answers a `Hello` from a version we no longer speak, we talk `oldest..=ours`.
+    Incompatible {
+        ours: u16,
+        oldest: u16,
+    },
+    /// A message was not acted on.
+    Error(ErrorReport),
+}
+
+/// Why the message with id `message_id` was not acted on.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ErrorReport {
+    pub code: ErrorCode,
+    pub message_id: u8,
+}
+
+impl ErrorReport {
+    /// What to send a peer on `peer_version`. Peers from before `Status::Error` asked each other to
+    /// drop the AES key when a sealed message did not open, and knew a sensor failure as a `Status`
+    /// of its own. Nothing else has a form they read, `ComsManager::send` drops it.
+    pub fn into_data(mut self, peer_version: u16) -> Data {
+        self.code = self.code.downgrade(peer_version);
+        if peer_version < 2 {
+            match self.code {
+                ErrorCode::DecryptFailed | ErrorCode::AuthFailed => {
+                    return Data::Command(Command::DeleteAESKey)
+                }
+                ErrorCode::SensorFailed => return Data::Status(Status::SensorFailed),
+                _ => {}
+            }
+        }
+        Data::Status(Status::Error(self))
+    }
+}
+
+/// The codes are part of the protocol, new ones go at the end.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A sealed message was authentic but did not decode, the sender should rekey.
+    DecryptFailed,
+    /// A sealed message failed its tag check, wrong key or altered on the way. The sender should
+    /// rekey. Only older nodes send this, newer ones drop such a message without a word.
+    AuthFailed,
+    /// The node cannot do that, the capabilities in its `Hello` say what it can.
+    UnsupportedCommand,
+    /// The EEPROM did not take the write.
+    Eeprom,
+    /// Stored key material failed its checks.
+    KeyCorrupt,
+    /// The node is already in the middle of that, try again later.
+    Busy,
+    /// The temperature sensor or the supply measurement could not be read.
+    SensorFailed,
+    /// The value does not fit the space the node keeps for it on the EEPROM.
+    NoRoom,
+}
+
+impl ErrorCode {
+    /// The protocol version that introduced this code.
+    pub fn since(self) -> u16 {
+        match self {
+            ErrorCode::NoRoom => 4,
+            _ => 1,
+        }
+    }
+
+    /// The closest code a peer on `peer_version` knows.
+    pub fn downgrade(self, peer_version: u16) -> ErrorCode {
+        match self {
+            ErrorCode::NoRoom if peer_version < 4 => ErrorCode::Eeprom,
+            code => code,
+        }
+    }
+}
+
+/// Outcome of wiping key material. Each field has one bit per key store slot, in the order own AES
+/// key, own key pair, peer AES key, peer public key.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct WipeReport {
+    pub wiped: u8,
+    pub failed: u8,
 }
 
+/// EEPROM wear, in write cycles, as counted by `wear`. `slots` follows the key store slot order used
+/// by `WipeReport`, `staging` is the shared update buffer and `endurance` is the rated cycle count.
+/// Each count is an upper bound on the cycles any byte of its region has taken since the part was
+/// new. Version 5 added the config, calibration and log counts.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct WearReport {
+    pub slots: [u32; 4],
+    pub staging: u32,
+    pub endurance: u32,
+    pub config: u32,
+    pub calibration: u32,
+    pub log: u32,
+}
+
+/// Key store cache counters since boot. `bytes_saved` is a lower bound on the EEPROM bytes, and so
+/// SPI transactions, that were served from RAM instead.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct CacheStats {
+    pub hits: u32,
+    pub misses: u32,
+    pub bytes_saved: u32,
+}
+
+/// A request to the peer, answered with a `Response`, see `rpc`. New commands go at the end.
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub enum Command {
     DeleteAESKey,
+    WipeAllKeys,
+    ReportWear,
+    ReportCacheStats,
+    /// Store a new config. The node answers with the config it now holds, which is the old one if
+    /// the new one does not fit, or with an `Eeprom` error.
+    SetConfig(Config),
+    GetConfig,
+    /// Send every entry of the event log, oldest first, then `Reply::LogDumped`.
+    /// `UnsupportedCommand` on nodes without a log.
+    DumpLog,
+    /// Store a new sensor calibration, answered with the calibration now in use.
+    /// `UnsupportedCommand` on nodes with no room for one.
+    SetCalibration(Calibration),
+    GetCalibration,
+    /// Sample the local sensor, answered with a `Reading`.
+    ReadTemperature,
+    /// Check the supply, the sensor and the identity keys, answered with a `SelfTestReport`.
+    SelfTest,
+}
+
+/// Names a `Command` without its arguments, what the firmware looks handlers up by.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandId {
+    DeleteAESKey,
+    WipeAllKeys,
+    ReportWear,
+    ReportCacheStats,
+    SetConfig,
+    GetConfig,
+    DumpLog,
+    SetCalibration,
+    GetCalibration,
+    ReadTemperature,
+    SelfTest,
+}
+
+impl Command {
+    pub fn id(&self) -> CommandId {
+        match self {
+            Command::DeleteAESKey => CommandId::DeleteAESKey,
+            Command::WipeAllKeys => CommandId::WipeAllKeys,
+            Command::ReportWear => CommandId::ReportWear,
+            Command::ReportCacheStats => CommandId::ReportCacheStats,
+            Command::SetConfig(_) => CommandId::SetConfig,
+            Command::GetConfig => CommandId::GetConfig,
+            Command::DumpLog => CommandId::DumpLog,
+            Command::SetCalibration(_) => CommandId::SetCalibration,
+            Command::GetCalibration => CommandId::GetCalibration,
+            Command::ReadTemperature => CommandId::ReadTemperature,
+            Command::SelfTest => CommandId::SelfTest,
+        }
+    }
+}
+
+/// The answer to the `Command` that came in the message with id `request_id`.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Response {
+    pub request_id: u8,
+    pub command: CommandId,
+    pub result: Result<Reply, ErrorCode>,
+}
+
+impl Response {
+    /// What to send a peer on `peer_version`. Peers from before `Data::Response` get the answers
+    /// they always got: the bare value, a `Status` or a `Status::Error`. A `WearReport` grew in
+    /// version 5 and older peers cannot read it any more, they are told the command is not there.
+    pub fn into_data(mut self, peer_version: u16) -> Data {
+        if peer_version < 5 && matches!(self.result, Ok(Reply::Wear(_))) {
+            self.result = Err(ErrorCode::UnsupportedCommand);
+        }
+        if let Err(code) = &mut self.result {
+            *code = code.downgrade(peer_version);
+        }
+        if peer_version >= 2 {
+            return Data::Response(self);
+        }
+        match self.result {
+            Ok(Reply::KeysWiped(report)) => Data::Status(Status::KeysWiped(report)),
+            Ok(Reply::CacheStats(stats)) => Data::CacheStats(stats),
+            Ok(Reply::Config(config)) => Data::Config(config),
+            Ok(Reply::Calibration(calibration)) => Data::Calibration(calibration),
+            Ok(Reply::Reading(reading)) => Data::Reading(reading),
+            Ok(Reply::LogDumped(sent)) => Data::Status(Status::LogDumped(sent)),
+            // no version 1 peer sends a command that answers with anything else, and the wear
+            // report is turned into an error above.
+            Ok(Reply::SelfTest(_) | Reply::Wear(_)) => ErrorReport {
+                code: ErrorCode::UnsupportedCommand,
+                message_id: self.request_id,
+            }
+            .into_data(peer_version),
+            Err(code) => ErrorReport {
+                code,
+                message_id: self.request_id,
+            }
+            .into_data(peer_version),
+        }
+    }
+}
+
+/// What a command produced, one variant per kind of answer.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub enum Reply {
+    KeysWiped(WipeReport),
+    Wear(WearReport),
+    CacheStats(CacheStats),
+    Config(Config),
+    Calibration(Calibration),
+    Reading(Reading),
+    /// Ends a `DumpLog` stream, with the number of entries that were sent.
+    LogDumped(u32),
+    SelfTest(SelfTestReport),
+}
+
+/// Outcome of `Command::SelfTest`. `failed` has one bit per check that did not pass.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct SelfTestReport {
+    /// The measured supply, 0 if it could not be read.
+    pub supply_mv: u16,
+    pub failed: u8,
+}
+
+impl SelfTestReport {
+    pub const SUPPLY: u8 = 1 << 0;
+    pub const SENSOR: u8 = 1 << 1;
+    pub const IDENTITY: u8 = 1 << 2;
+    /// Sealed messages that did not check out came in since boot, someone else is on the line or
+    /// the peer holds another key.
+    pub const LINK: u8 = 1 << 3;
+}
+
+/// A local sensor sample in °C, before and after calibration. Put the board next to a reference
+/// thermometer and `measured` is what goes into a `CalibrationPoint`, which keeps it in hundredths
+/// of a degree.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Reading {
+    pub measured: f32,
+    pub celsius: f32,
+}
+
+/// Something worth keeping for after the run.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub enum Event {
+    Boot,
+    /// The peer's temperature crossed the configured threshold, in °C.
+    OverTemperature(f32),
+    /// An encrypted message did not decrypt, the peer's AES key was dropped.
+    DecryptFailed,
+    KeysWiped(WipeReport),
+}
+
+/// One event log entry. `seq` keeps counting across resets, `millis` restarts at every boot.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    pub seq: u32,
+    pub millis: u32,
+    pub event: Event,
 }
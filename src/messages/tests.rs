@@ -0,0 +1,93 @@
+use super::*;
+
+fn report(code: ErrorCode) -> ErrorReport {
+    ErrorReport {
+        code,
+        message_id: 7,
+    }
+}
+
+fn response(result: Result<Reply, ErrorCode>) -> Response {
+    Response {
+        request_id: 7,
+        command: CommandId::SelfTest,
+        result,
+    }
+}
+
+#[test]
+fn status_error_is_not_sent_to_version_1_peers() {
+    let data = report(ErrorCode::Busy).into_data(1);
+    assert!(matches!(data, Data::Status(Status::Error(_))));
+    assert!(data.since() > 1);
+    assert!(response(Err(ErrorCode::Busy)).into_data(1).since() > 1);
+    assert_eq!(report(ErrorCode::Busy).into_data(2).since(), 2);
+}
+
+#[test]
+fn version_1_peers_get_the_reports_they_knew() {
+    for code in [ErrorCode::DecryptFailed, ErrorCode::AuthFailed] {
+        let data = report(code).into_data(1);
+        assert!(
+            matches!(data, Data::Command(Command::DeleteAESKey)),
+            "{data:?}"
+        );
+        assert!(matches!(
+            report(code).into_data(2),
+            Data::Status(Status::Error(ErrorReport { code: c, .. })) if c == code
+        ));
+    }
+    assert!(matches!(
+        response(Err(ErrorCode::SensorFailed)).into_data(1),
+        Data::Status(Status::SensorFailed)
+    ));
+    assert!(matches!(
+        response(Ok(Reply::LogDumped(3))).into_data(1),
+        Data::Status(Status::LogDumped(3))
+    ));
+}
+
+#[test]
+fn no_room_is_an_eeprom_error_before_version_4() {
+    for version in 1..4 {
+        let data = response(Err(ErrorCode::NoRoom)).into_data(version);
+        assert!(data.since() <= version.max(2), "{data:?}");
+        assert!(
+            matches!(
+                data,
+                Data::Response(Response {
+                    result: Err(ErrorCode::Eeprom),
+                    ..
+                }) | Data::Status(Status::Error(ErrorReport {
+                    code: ErrorCode::Eeprom,
+                    ..
+                }))
+            ),
+            "{data:?}"
+        );
+    }
+    let data = response(Err(ErrorCode::NoRoom)).into_data(4);
+    assert_eq!(data.since(), 4);
+    assert_eq!(report(ErrorCode::NoRoom).into_data(4).since(), 4);
+}
+
+#[test]
+fn peers_before_version_5_are_not_sent_the_larger_wear_report() {
+    let wear = || response(Ok(Reply::Wear(WearReport::default())));
+    for version in 2..5 {
+        let data = wear().into_data(version);
+        assert!(data.since() <= version, "{data:?}");
+        assert!(
+            matches!(
+                data,
+                Data::Response(Response {
+                    result: Err(ErrorCode::UnsupportedCommand),
+                    ..
+                })
+            ),
+            "{data:?}"
+        );
+    }
+    assert_eq!(wear().into_data(5).since(), 5);
+    assert_eq!(Data::Wear(WearReport::default()).since(), 5);
+}
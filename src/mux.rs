@@ -1,105 +1,57 @@
 //! Multiplexer
+//!
+//! The mux routes a single I/O line to one of its channels, picked by the S0/S1 select pins. The
+//! driver only needs `OutputPin`s for the selects and an `IoLine` for the shared line, so it runs
+//! against the board pins here or the mocks in the host tests.
 
+use core::convert::Infallible;
 use cortex_m::interrupt::free;
-use stm32f0xx_hal::adc::Adc;
-use stm32f0xx_hal::gpio::gpioa::{PA0, PA1, PA2};
+use embedded_hal::digital::v2::ToggleableOutputPin;
+use stm32f0xx_hal::adc::{Adc, VRef};
+use stm32f0xx_hal::gpio::gpioa::PA2;
 use stm32f0xx_hal::gpio::{Output, PushPull};
 use stm32f0xx_hal::prelude::*;
 
-#[derive(Clone, Copy)]
-pub enum Channel {
-    RedLED,
-    GreenLED,
-    TempSensor,
-}
-
-struct Selector {
-    s0: PA1<Output<PushPull>>,
-    s1: PA0<Output<PushPull>>,
-}
+mod driver;
 
-impl Selector {
-    pub fn new(s0: PA1<Output<PushPull>>, s1: PA0<Output<PushPull>>) -> Selector {
-        Selector { s0, s1 }
-    }
-
-    pub fn select(&mut self, channel: Channel) {
-        match channel {
-            Channel::RedLED => {
-                // unwrap since this is an infallible error
-                self.s0.set_low().unwrap();
-                self.s1.set_low().unwrap();
-            }
-            Channel::GreenLED => {
-                self.s0.set_high().unwrap();
-                self.s1.set_low().unwrap();
-            }
-            Channel::TempSensor => {
-                self.s0.set_low().unwrap();
-                self.s1.set_high().unwrap();
-            }
-        }
-    }
-}
+pub use driver::*;
 
-pub struct Mux {
-    selector: Selector,
+/// PA2 and the ADC on the board.
+pub struct AdcIo {
     io: Option<PA2<Output<PushPull>>>, // we will need to take and transform the pin, so we use an option.
     adc: Adc,
 }
 
-impl Mux {
-    pub fn new(
-        s0: PA1<Output<PushPull>>,
-        s1: PA0<Output<PushPull>>,
-        io: PA2<Output<PushPull>>,
-        adc: Adc,
-    ) -> Mux {
-        Mux {
-            selector: Selector::new(s0, s1),
-            io: Some(io),
-            adc,
-        }
+impl AdcIo {
+    pub fn new(io: PA2<Output<PushPull>>, adc: Adc) -> AdcIo {
+        AdcIo { io: Some(io), adc }
     }
+}
 
-    pub fn select(&mut self, channel: Channel) {
-        self.selector.select(channel);
-    }
+impl ToggleableOutputPin for AdcIo {
+    type Error = Infallible;
 
-    pub fn take(&mut self) -> Option<PA2<Output<PushPull>>> {
-        self.io.take()
+    fn toggle(&mut self) -> Result<(), Infallible> {
+        if let Some(io) = self.io.as_mut() {
+            io.toggle()?;
+        }
+        Ok(())
     }
+}
 
-    pub fn give(&mut self, io: PA2<Output<PushPull>>) {
-        self.io = Some(io);
+impl IoLine for AdcIo {
+    fn read_analog(&mut self) -> Option<u16> {
+        // take the pin and convert to an analog pin
+        let io = self.io.take()?;
+        free(|cs| {
+            let mut adc_pin = io.into_analog(cs);
+            let val: Option<u16> = self.adc.read(&mut adc_pin).ok();
+            self.io = Some(adc_pin.into_push_pull_output(cs));
+            val
+        })
     }
 
-    pub fn execute(&mut self, channel: Channel) -> Option<u16> {
-        let mut ret_val = None;
-        self.select(channel);
-        match channel {
-            Channel::GreenLED | Channel::RedLED => {
-                if let Some(mut io) = self.take() {
-                    io.toggle().unwrap();
-                    self.give(io);
-                }
-                return None;
-            }
-            Channel::TempSensor => {
-                // take the pin and convert to an analog pin
-                if let Some(mut io) = self.take() {
-                    let ret_val_ref = &mut ret_val;
-                    free(move |cs| {
-                        let mut adc_pin = io.into_analog(cs);
-                        if let Ok(val) = self.adc.read(&mut adc_pin) as Result<u16, _> {
-                            *ret_val_ref = Some(val);
-                        }
-                        io = adc_pin.into_push_pull_output(cs);
-                        self.give(io);
-                    })
-                }
-            }
-        }
-        ret_val
+    fn read_supply_mv(&mut self) -> Option<u16> {
+        Some(VRef::read_vdda(&mut self.adc))
     }
 }
@@ -0,0 +1,109 @@
+//! The mux itself, generic over its pins
+//!
+//! Nothing here touches the HAL, so the host tests build it against the mocks in `mock`.
+
+use core::convert::Infallible;
+use embedded_hal::digital::v2::{OutputPin, ToggleableOutputPin};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    RedLED,
+    GreenLED,
+    TempSensor,
+}
+
+impl Channel {
+    #[cfg(test)]
+    pub const ALL: [Channel; 3] = [Channel::RedLED, Channel::GreenLED, Channel::TempSensor];
+
+    /// Levels of the (S0, S1) select pins that route the I/O line to this channel.
+    pub const fn select_pattern(self) -> (bool, bool) {
+        match self {
+            Channel::RedLED => (false, false),
+            Channel::GreenLED => (true, false),
+            Channel::TempSensor => (false, true),
+        }
+    }
+}
+
+/// The line shared by every channel: driven as a digital output for the LEDs, read by the ADC for
+/// the sensor.
+pub trait IoLine: ToggleableOutputPin<Error = Infallible> {
+    /// One conversion of the line's voltage, `None` if the ADC failed.
+    fn read_analog(&mut self) -> Option<u16>;
+
+    /// The ADC's supply in millivolts, measured against the internal reference.
+    fn read_supply_mv(&mut self) -> Option<u16>;
+}
+
+struct Selector<S0, S1> {
+    s0: S0,
+    s1: S1,
+}
+
+impl<S0, S1> Selector<S0, S1>
+where
+    S0: OutputPin<Error = Infallible>,
+    S1: OutputPin<Error = Infallible>,
+{
+    pub fn new(s0: S0, s1: S1) -> Selector<S0, S1> {
+        Selector { s0, s1 }
+    }
+
+    pub fn select(&mut self, channel: Channel) {
+        let (s0, s1) = channel.select_pattern();
+        // unwrap since this is an infallible error
+        self.s0.set_state(s0.into()).unwrap();
+        self.s1.set_state(s1.into()).unwrap();
+    }
+
+    #[cfg(test)]
+    pub fn release(self) -> (S0, S1) {
+        (self.s0, self.s1)
+    }
+}
+
+pub struct Mux<S0, S1, Io> {
+    selector: Selector<S0, S1>,
+    io: Io,
+}
+
+impl<S0, S1, Io> Mux<S0, S1, Io>
+where
+    S0: OutputPin<Error = Infallible>,
+    S1: OutputPin<Error = Infallible>,
+    Io: IoLine,
+{
+    pub fn new(s0: S0, s1: S1, io: Io) -> Mux<S0, S1, Io> {
+        Mux {
+            selector: Selector::new(s0, s1),
+            io,
+        }
+    }
+
+    pub fn select(&mut self, channel: Channel) {
+        self.selector.select(channel);
+    }
+
+    pub fn execute(&mut self, channel: Channel) -> Option<u16> {
+        self.select(channel);
+        match channel {
+            Channel::GreenLED | Channel::RedLED => {
+                self.io.toggle().unwrap();
+                None
+            }
+            Channel::TempSensor => self.io.read_analog(),
+        }
+    }
+
+    pub fn supply_mv(&mut self) -> Option<u16> {
+        self.io.read_supply_mv()
+    }
+
+    /// Give the pins back to inspect the mocks.
+    #[cfg(test)]
+    pub fn release(self) -> (S0, S1, Io) {
+        let (s0, s1) = self.selector.release();
+        (s0, s1, self.io)
+    }
+}
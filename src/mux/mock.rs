@@ -0,0 +1,99 @@
+//! Mock pins for driving the mux off target
+//!
+//! `MockPin` remembers its level and every change, `MockIo` counts toggles and hands out a canned
+//! ADC reading. Only the host tests build them.
+
+use core::convert::Infallible;
+use embedded_hal::digital::v2::{OutputPin, ToggleableOutputPin};
+
+use super::IoLine;
+
+/// Output pin that starts low and logs every level it is set to.
+#[derive(Default)]
+pub struct MockPin {
+    high: bool,
+    history: heapless::Vec<bool, 16>,
+}
+
+impl MockPin {
+    pub fn new() -> MockPin {
+        MockPin::default()
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+
+    /// Levels set so far, oldest first. Only the first 16 are kept.
+    pub fn history(&self) -> &[bool] {
+        &self.history
+    }
+
+    fn set(&mut self, high: bool) {
+        self.high = high;
+        self.history.push(high).ok();
+    }
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.set(true);
+        Ok(())
+    }
+}
+
+/// I/O line with a toggle counter and a fixed ADC reading.
+#[derive(Default)]
+pub struct MockIo {
+    toggles: u32,
+    reads: u32,
+    reading: Option<u16>,
+}
+
+impl MockIo {
+    /// The supply every mock line reports.
+    pub const SUPPLY_MV: u16 = 3_300;
+
+    /// `reading` is returned by every conversion, `None` to simulate an ADC error.
+    pub fn new(reading: Option<u16>) -> MockIo {
+        MockIo {
+            reading,
+            ..MockIo::default()
+        }
+    }
+
+    pub fn toggles(&self) -> u32 {
+        self.toggles
+    }
+
+    pub fn reads(&self) -> u32 {
+        self.reads
+    }
+}
+
+impl ToggleableOutputPin for MockIo {
+    type Error = Infallible;
+
+    fn toggle(&mut self) -> Result<(), Infallible> {
+        self.toggles += 1;
+        Ok(())
+    }
+}
+
+impl IoLine for MockIo {
+    fn read_analog(&mut self) -> Option<u16> {
+        self.reads += 1;
+        self.reading
+    }
+
+    fn read_supply_mv(&mut self) -> Option<u16> {
+        Some(MockIo::SUPPLY_MV)
+    }
+}
@@ -0,0 +1,42 @@
+use super::mock::{MockIo, MockPin};
+use super::*;
+
+const READING: u16 = 0x0ABC;
+
+fn execute(channel: Channel) -> (Option<u16>, MockPin, MockPin, MockIo) {
+    let mut mux = Mux::new(MockPin::new(), MockPin::new(), MockIo::new(Some(READING)));
+    let result = mux.execute(channel);
+    let (s0, s1, io) = mux.release();
+    (result, s0, s1, io)
+}
+
+#[test]
+fn every_channel_sets_its_select_pattern() {
+    for channel in Channel::ALL {
+        let (_, s0, s1, _) = execute(channel);
+        assert_eq!((s0.is_high(), s1.is_high()), channel.select_pattern());
+    }
+}
+
+#[test]
+fn the_leds_toggle_the_line_once() {
+    for channel in [Channel::RedLED, Channel::GreenLED] {
+        let (result, _, _, io) = execute(channel);
+        assert_eq!(result, None);
+        assert_eq!((io.toggles(), io.reads()), (1, 0));
+    }
+}
+
+#[test]
+fn the_sensor_reads_the_line_once() {
+    let (result, _, _, io) = execute(Channel::TempSensor);
+    assert_eq!(result, Some(READING));
+    assert_eq!((io.toggles(), io.reads()), (0, 1));
+}
+
+#[test]
+fn an_adc_error_reads_as_none() {
+    let mut mux = Mux::new(MockPin::new(), MockPin::new(), MockIo::new(None));
+    assert_eq!(mux.execute(Channel::TempSensor), None);
+    assert_eq!(mux.supply_mv(), Some(MockIo::SUPPLY_MV));
+}
@@ -0,0 +1,110 @@
+//! The node's state
+//!
+//! Everything the main loop and the command handlers work on, built once at boot. The link lives in
+//! `COMS` so the USART interrupt can reach it, `coms` and `send` go through it.
+
+use core::ops::DerefMut;
+use stm32f0xx_hal::gpio::gpioa::{PA0, PA1};
+use stm32f0xx_hal::gpio::{Output, PushPull};
+
+use crate::clock;
+use crate::coms_manager::ComsManager;
+use crate::config::Config;
+use crate::crypt;
+use crate::eeprom::EepromManager;
+use crate::event_log::EventLog;
+use crate::key_store::{KeyStore, OwnAesKey, OwnKeyPair, PeerAesKey, PeerPublicKey};
+use crate::messages::{Data, ErrorCode, ErrorReport, Event, Status};
+use crate::mux::{AdcIo, Mux};
+use crate::session::{Action, Session};
+use crate::telemetry::Scheduler;
+use crate::temperature::TemperatureSensor;
+use crate::wear::Counted;
+use crate::COMS;
+
+pub type BoardMux = Mux<PA1<Output<PushPull>>, PA0<Output<PushPull>>, AdcIo>;
+
+pub struct Node {
+    pub key_store: KeyStore<Counted<EepromManager>>,
+    pub config: Config,
+    pub sensor: TemperatureSensor,
+    pub mux: BoardMux,
+    /// `None` on a part without room for the log, the node then runs without one.
+    pub event_log: Option<EventLog>,
+    pub telemetry: Scheduler,
+    pub session: Session,
+    /// What the key exchange asked for while a command ran. It is carried out once the command's
+    /// `Response` has gone out, sealed under the key the command came in with.
+    pub after_response: Action,
+    /// Only log the moment the peer goes over the threshold, not every reading after that.
+    pub over_temperature: bool,
+}
+
+impl Node {
+    pub fn log(&mut self, event: &Event) {
+        if let Some(event_log) = &mut self.event_log {
+            event_log
+                .append(self.key_store.storage(), clock::millis(), event)
+                .ok();
+        }
+    }
+
+    /// Carry out what the key exchange asked for, then hand the link the key to seal traffic with.
+    pub fn perform(&mut self, action: Action) {
+        let key_store = &mut self.key_store;
+        let msg = match action {
+            Action::Nothing => None,
+            Action::RequestPublicKey => Some(Data::Status(Status::UnkownPublicKey)),
+            Action::SendPublicKey => match key_store.get(OwnKeyPair) {
+                Ok(Some(key_pair)) => Some(Data::RSAPublicKey(key_pair.public())),
+                _ => None,
+            },
+            Action::RequestSessionKey => Some(Data::Status(Status::UnkownAESKey)),
+            // the key only goes out encrypted for the peer, which decrypts it with its private key.
+            Action::SendSessionKey => {
+                match (key_store.get(OwnAesKey), key_store.get(PeerPublicKey)) {
+                    (Ok(Some(key)), Ok(Some(peer_public_key))) => {
+                        Some(Data::AESKey(crypt::encrypt(&peer_public_key, &*key)))
+                    }
+                    _ => None,
+                }
+            }
+            Action::AdoptPeerSessionKey => {
+                if let Ok(Some(key)) = key_store.get(PeerAesKey) {
+                    key_store.put(OwnAesKey, &key).ok();
+                }
+                None
+            }
+        };
+
+        // both sides hold the same key once established, so our own key also opens what the peer
+        // sends.
+        let key = match self.session.is_established() {
+            true => key_store.get(OwnAesKey).ok().flatten(),
+            false => None,
+        };
+        coms(|coms_manager| {
+            coms_manager.set_session_key(key.as_deref());
+            if let Some(msg) = msg {
+                coms_manager.send(&msg);
+            }
+        });
+    }
+}
+
+/// Run `f` on the link, `None` until it is up.
+pub fn coms<R>(f: impl FnOnce(&mut ComsManager) -> R) -> Option<R> {
+    cortex_m::interrupt::free(|cs| COMS.borrow(cs).borrow_mut().deref_mut().as_mut().map(f))
+}
+
+pub fn send(data: &Data) {
+    coms(|coms_manager| coms_manager.send(data));
+}
+
+/// Tell the peer why the message with `message_id` was not acted on.
+pub fn report(code: ErrorCode, message_id: u8) {
+    coms(|coms_manager| {
+        let report = ErrorReport { code, message_id };
+        coms_manager.send(&report.into_data(coms_manager.peer_version()));
+    });
+}
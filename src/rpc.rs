@@ -0,0 +1,45 @@
+//! Request/response layer for commands
+//!
+//! A `Data::Command` is a request: the variant names the command and carries its arguments, and the
+//! id of the message it came in is the request id. The firmware looks the command up in a
+//! `Registry` and wraps whatever the handler returns in a `Response` with that id, so the sender can
+//! match answers to requests even when several are in flight.
+//!
+//! Adding a command takes a `Command` variant, its `CommandId` and an entry in the registry, see
+//! `commands`. A command without an entry is answered with `UnsupportedCommand`.
+
+use crate::messages::{Command, CommandId, ErrorCode, Reply, Response};
+
+/// Serves one command, with the state `C` it needs.
+pub type Handler<C> = fn(&mut C, Command) -> Result<Reply, ErrorCode>;
+
+pub struct Registry<C: 'static> {
+    handlers: &'static [(CommandId, Handler<C>)],
+}
+
+impl<C> Registry<C> {
+    pub const fn new(handlers: &'static [(CommandId, Handler<C>)]) -> Registry<C> {
+        Registry { handlers }
+    }
+
+    pub fn handler(&self, id: CommandId) -> Option<Handler<C>> {
+        self.handlers
+            .iter()
+            .find(|(entry, _)| *entry == id)
+            .map(|(_, handler)| *handler)
+    }
+
+    /// Run the handler for `command` and correlate its result with `request_id`.
+    pub fn dispatch(&self, context: &mut C, request_id: u8, command: Command) -> Response {
+        let id = command.id();
+        let result = match self.handler(id) {
+            Some(handler) => handler(context, command),
+            None => Err(ErrorCode::UnsupportedCommand),
+        };
+        Response {
+            request_id,
+            command: id,
+            result,
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! Machine-readable description of the wire format
+//!
+//! `write_spec` walks `Message` through serde and writes every type it meets as JSON: structs with
+//! their fields in encoding order, enums with the postcard index of each variant. Nothing in it is
+//! written by hand, so it cannot drift from `messages.rs`. Host tools read the copy in
+//! `protocol/schema.json`, the tests fail once that copy is stale.
+//!
+//! Tracing deserializes `Message` from a deserializer that makes up every value. Each pass takes a
+//! variant of every enum it meets, one that has not been seen yet if there is one, and passes
+//! repeat until every variant of every enum has been seen.
+
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::Deserialize;
+
+use crate::messages::{Message, PROTOCOL_VERSION};
+
+pub mod json;
+#[cfg(test)]
+mod tests;
+
+/// A type as it appears in the spec, as JSON: `"u8"`, `"Config"`, `{"seq": "u8"}`,
+/// `{"array": ["u8", 8]}`, `{"option": "u32"}` or `{"tuple": [..]}`.
+pub type Format = String<64>;
+type Formats = Vec<Format, MAX_FIELDS>;
+
+const MAX_CONTAINERS: usize = 48;
+const MAX_FIELDS: usize = 16;
+const MAX_VARIANTS: usize = 24;
+const MAX_TOTAL_FIELDS: usize = 256;
+/// More than enough for every enum to come up with each of its variants.
+const MAX_PASSES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// More types, fields or variants than the tracer has room for.
+    Capacity,
+    /// Serde asked for something postcard cannot encode, like a map.
+    Unsupported,
+    /// Some enum variant never came up.
+    Incomplete,
+    Custom,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl de::StdError for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error::Custom
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Error::Capacity
+    }
+}
+
+/// How the fields of a struct or an enum variant are laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Unit,
+    Newtype,
+    Tuple,
+    Struct,
+}
+
+/// A layout, and where its fields sit in `Registry::fields`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub layout: Layout,
+    start: u16,
+    len: u8,
+}
+
+/// One field of a struct or variant, `name` is empty unless the layout is `Struct`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub format: Format,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    Struct(Shape),
+    /// Every variant in index order, with its shape once it has been seen.
+    Enum {
+        variants: &'static [&'static str],
+        shapes: Vec<Option<Shape>, MAX_VARIANTS>,
+        visits: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+/// Every named type met so far, in the order they came up. Each type and variant is written down
+/// the first time it comes up only, later passes do not add to it.
+#[derive(Default)]
+pub struct Registry {
+    containers: Vec<Container, MAX_CONTAINERS>,
+    fields: Vec<Field, MAX_TOTAL_FIELDS>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    pub fn containers(&self) -> &[Container] {
+        &self.containers
+    }
+
+    pub fn fields(&self, shape: Shape) -> &[Field] {
+        &self.fields[shape.start as usize..][..shape.len as usize]
+    }
+
+    /// Trace `T` and everything it contains, answers with the format of `T` itself.
+    pub fn trace<'de, T: Deserialize<'de>>(&mut self) -> Result<Format, Error> {
+        for _ in 0..MAX_PASSES {
+            let mut format = Format::new();
+            T::deserialize(Tracer {
+                registry: self,
+                format: &mut format,
+            })?;
+            if self.complete() {
+                return Ok(format);
+            }
+        }
+        Err(Error::Incomplete)
+    }
+
+    fn complete(&self) -> bool {
+        self.containers
+            .iter()
+            .all(|container| match &container.kind {
+                Kind::Struct(_) => true,
+                Kind::Enum { shapes, .. } => shapes.iter().all(Option::is_some),
+            })
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.containers.iter().position(|c| c.name == name)
+    }
+
+    fn shape(
+        &mut self,
+        layout: Layout,
+        names: &'static [&'static str],
+        formats: Formats,
+    ) -> Result<Shape, Error> {
+        let start = self.fields.len();
+        for (i, format) in formats.into_iter().enumerate() {
+            let name = names.get(i).copied().unwrap_or("");
+            self.fields
+                .push(Field { name, format })
+                .map_err(|_| Error::Capacity)?;
+        }
+        Ok(Shape {
+            layout,
+            start: start as u16,
+            len: (self.fields.len() - start) as u8,
+        })
+    }
+
+    fn define(
+        &mut self,
+        name: &'static str,
+        layout: Layout,
+        names: &'static [&'static str],
+        formats: Formats,
+    ) -> Result<(), Error> {
+        if self.position(name).is_some() {
+            return Ok(());
+        }
+        let kind = Kind::Struct(self.shape(layout, names, formats)?);
+        self.containers
+            .push(Container { name, kind })
+            .map_err(|_| Error::Capacity)
+    }
+
+    /// Register the enum if it is new and pick the variant to trace this time.
+    fn pick(
+        &mut self,
+        name: &'static str,
+        variants: &'static [&'static str],
+    ) -> Result<(usize, u32), Error> {
+        let i = match self.position(name) {
+            Some(i) => i,
+            None => {
+                let mut shapes = Vec::new();
+                for _ in variants {
+                    shapes.push(None).map_err(|_| Error::Capacity)?;
+                }
+                self.containers
+                    .push(Container {
+                        name,
+                        kind: Kind::Enum {
+                            variants,
+                            shapes,
+                            visits: 0,
+                        },
+                    })
+                    .map_err(|_| Error::Capacity)?;
+                self.containers.len() - 1
+            }
+        };
+        let Kind::Enum { shapes, visits, .. } = &mut self.containers[i].kind else {
+            return Err(Error::Custom);
+        };
+        // once every variant is known keep cycling through them, the enums inside get their turn.
+        let index = shapes
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(*visits % shapes.len().max(1));
+        *visits += 1;
+        Ok((i, index as u32))
+    }
+
+    fn variant(
+        &mut self,
+        container: usize,
+        index: u32,
+        layout: Layout,
+        names: &'static [&'static str],
+        formats: Formats,
+    ) -> Result<(), Error> {
+        match &self.containers[container].kind {
+            Kind::Enum { shapes, .. } if shapes[index as usize].is_none() => {}
+            _ => return Ok(()),
+        }
+        let shape = self.shape(layout, names, formats)?;
+        if let Kind::Enum { shapes, .. } = &mut self.containers[container].kind {
+            shapes[index as usize] = Some(shape);
+        }
+        Ok(())
+    }
+}
+
+/// Write the spec of `Message` as JSON, one type per line and one line per enum variant.
+pub fn write_spec<W: Write>(out: &mut W) -> Result<(), Error> {
+    let mut registry = Registry::new();
+    let root = registry.trace::<Message>()?;
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"encoding\": \"postcard\",")?;
+    writeln!(out, "  \"protocol_version\": {},", PROTOCOL_VERSION)?;
+    writeln!(out, "  \"root\": {},", root)?;
+    writeln!(out, "  \"types\": [")?;
+    let count = registry.containers().len();
+    for (i, container) in registry.containers().iter().enumerate() {
+        let comma = if i + 1 < count { "," } else { "" };
+        match &container.kind {
+            Kind::Struct(shape) => {
+                write!(out, "    {{\"name\": \"{}\", ", container.name)?;
+                write_shape(out, &registry, *shape)?;
+                writeln!(out, "}}{}", comma)?;
+            }
+            Kind::Enum {
+                variants, shapes, ..
+            } => {
+                writeln!(out, "    {{\"name\": \"{}\", \"enum\": [", container.name)?;
+                for (index, (name, shape)) in variants.iter().zip(shapes).enumerate() {
+                    write!(
+                        out,
+                        "      {{\"index\": {}, \"name\": \"{}\", ",
+                        index, name
+                    )?;
+                    write_shape(out, &registry, shape.ok_or(Error::Incomplete)?)?;
+                    let comma = if index + 1 < variants.len() { "," } else { "" };
+                    writeln!(out, "}}{}", comma)?;
+                }
+                writeln!(out, "    ]}}{}", comma)?;
+            }
+        }
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn write_shape<W: Write>(out: &mut W, registry: &Registry, shape: Shape) -> fmt::Result {
+    let fields = registry.fields(shape);
+    match shape.layout {
+        Layout::Unit => write!(out, "\"unit\": null"),
+        Layout::Newtype => write!(out, "\"newtype\": {}", fields[0].format),
+        Layout::Tuple => {
+            write!(out, "\"tuple\": [")?;
+            write_list(out, fields.iter().map(|field| (None, &field.format)))?;
+            write!(out, "]")
+        }
+        Layout::Struct => {
+            write!(out, "\"struct\": [")?;
+            write_list(
+                out,
+                fields.iter().map(|field| (Some(field.name), &field.format)),
+            )?;
+            write!(out, "]")
+        }
+    }
+}
+
+fn write_list<'a, W: Write>(
+    out: &mut W,
+    items: impl Iterator<Item = (Option<&'a str>, &'a Format)>,
+) -> fmt::Result {
+    for (i, (name, format)) in items.enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        match name {
+            Some(name) => write!(out, "{{\"name\": \"{}\", \"type\": {}}}", name, format)?,
+            None => write!(out, "{}", format)?,
+        }
+    }
+    Ok(())
+}
+
+/// Makes up a value for whatever it is asked for and writes down what that was.
+struct Tracer<'r> {
+    registry: &'r mut Registry,
+    format: &'r mut Format,
+}
+
+impl Tracer<'_> {
+    fn primitive(&mut self, name: &str) -> Result<(), Error> {
+        self.format.clear();
+        write!(self.format, "\"{}\"", name)?;
+        Ok(())
+    }
+}
+
+macro_rules! primitive {
+    ($($method:ident => $visit:ident($($value:expr)?), $name:literal;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+                self.primitive($name)?;
+                visitor.$visit($($value)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Tracer<'_> {
+    type Error = Error;
+
+    primitive! {
+        deserialize_bool => visit_bool(false), "bool";
+        deserialize_i8 => visit_i8(0), "i8";
+        deserialize_i16 => visit_i16(0), "i16";
+        deserialize_i32 => visit_i32(0), "i32";
+        deserialize_i64 => visit_i64(0), "i64";
+        deserialize_i128 => visit_i128(0), "i128";
+        deserialize_u8 => visit_u8(0), "u8";
+        deserialize_u16 => visit_u16(0), "u16";
+        deserialize_u32 => visit_u32(0), "u32";
+        deserialize_u64 => visit_u64(0), "u64";
+        deserialize_u128 => visit_u128(0), "u128";
+        deserialize_f32 => visit_f32(0.0), "f32";
+        deserialize_f64 => visit_f64(0.0), "f64";
+        deserialize_char => visit_char('\0'), "char";
+        deserialize_str => visit_str(""), "str";
+        deserialize_string => visit_str(""), "str";
+        deserialize_bytes => visit_bytes(&[]), "bytes";
+        deserialize_byte_buf => visit_bytes(&[]), "bytes";
+        deserialize_unit => visit_unit(), "unit";
+        deserialize_ignored_any => visit_unit(), "unit";
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut inner = Format::new();
+        let value = visitor.visit_some(Tracer {
+            registry: self.registry,
+            format: &mut inner,
+        })?;
+        self.format.clear();
+        write!(self.format, "{{\"option\": {}}}", inner)?;
+        Ok(value)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.registry.define(name, Layout::Unit, &[], Vec::new())?;
+        named(self.format, name)?;
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut inner = Format::new();
+        let value = visitor.visit_newtype_struct(Tracer {
+            registry: self.registry,
+            format: &mut inner,
+        })?;
+        let formats = Vec::from_iter([inner]);
+        self.registry.define(name, Layout::Newtype, &[], formats)?;
+        named(self.format, name)?;
+        Ok(value)
+    }
+
+    /// One element is enough to know the element type.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            registry: self.registry,
+            remaining: 1,
+            formats: &mut formats,
+        })?;
+        self.format.clear();
+        match formats.first() {
+            Some(element) => write!(self.format, "{{\"seq\": {}}}", element)?,
+            None => return Err(Error::Custom),
+        }
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            registry: self.registry,
+            remaining: len,
+            formats: &mut formats,
+        })?;
+        self.format.clear();
+        match formats.first() {
+            // arrays list their element type once.
+            Some(first) if formats.iter().all(|format| format == first) => {
+                write!(self.format, "{{\"array\": [{}, {}]}}", first, formats.len())?
+            }
+            _ => {
+                write!(self.format, "{{\"tuple\": [")?;
+                write_list(self.format, formats.iter().map(|format| (None, format)))?;
+                write!(self.format, "]}}")?;
+            }
+        }
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            registry: self.registry,
+            remaining: len,
+            formats: &mut formats,
+        })?;
+        self.registry.define(name, Layout::Tuple, &[], formats)?;
+        named(self.format, name)?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            registry: self.registry,
+            remaining: fields.len(),
+            formats: &mut formats,
+        })?;
+        self.registry
+            .define(name, Layout::Struct, fields, formats)?;
+        named(self.format, name)?;
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let (container, index) = self.registry.pick(name, variants)?;
+        let value = visitor.visit_enum(Variant {
+            registry: self.registry,
+            container,
+            index,
+        })?;
+        named(self.format, name)?;
+        Ok(value)
+    }
+}
+
+fn named(format: &mut Format, name: &str) -> Result<(), Error> {
+    format.clear();
+    write!(format, "\"{}\"", name)?;
+    Ok(())
+}
+
+/// Hands out `remaining` made up elements and keeps their formats.
+struct Elements<'r> {
+    registry: &'r mut Registry,
+    remaining: usize,
+    formats: &'r mut Formats,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let mut format = Format::new();
+        let value = seed.deserialize(Tracer {
+            registry: self.registry,
+            format: &mut format,
+        })?;
+        self.formats.push(format).map_err(|_| Error::Capacity)?;
+        Ok(Some(value))
+    }
+}
+
+/// The variant `pick` chose, its shape is recorded once serde says what it is.
+struct Variant<'r> {
+    registry: &'r mut Registry,
+    container: usize,
+    index: u32,
+}
+
+impl<'de, 'r> de::EnumAccess<'de> for Variant<'r> {
+    type Error = Error;
+    type Variant = Variant<'r>;
+
+    fn variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, Self), Error> {
+        let index: de::value::U32Deserializer<Error> = self.index.into_deserializer();
+        let value = seed.deserialize(index)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        self.registry
+            .variant(self.container, self.index, Layout::Unit, &[], Vec::new())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        let mut inner = Format::new();
+        let value = seed.deserialize(Tracer {
+            registry: self.registry,
+            format: &mut inner,
+        })?;
+        let formats = Vec::from_iter([inner]);
+        self.registry
+            .variant(self.container, self.index, Layout::Newtype, &[], formats)?;
+        Ok(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            registry: self.registry,
+            remaining: len,
+            formats: &mut formats,
+        })?;
+        self.registry
+            .variant(self.container, self.index, Layout::Tuple, &[], formats)?;
+        Ok(value)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut formats = Vec::new();
+        let value = visitor.visit_seq(Elements {
+            registry: self.registry,
+            remaining: fields.len(),
+            formats: &mut formats,
+        })?;
+        self.registry
+            .variant(self.container, self.index, Layout::Struct, fields, formats)?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,354 @@
+//! Messages as JSON
+//!
+//! A serde serializer that writes compact JSON to any `fmt::Write`. Enums are written the usual
+//! serde way: a unit variant as its name, any other as `{"Variant": value}`. Floats that JSON cannot
+//! hold, NaN and the infinities, come out as `null`.
+
+use core::fmt::{self, Write};
+use serde::ser::{self, Serialize};
+
+use super::Error;
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Error::Custom
+    }
+}
+
+pub fn write<W: Write, T: Serialize + ?Sized>(out: &mut W, value: &T) -> Result<(), Error> {
+    value.serialize(Json { out })
+}
+
+struct Json<'w, W> {
+    out: &'w mut W,
+}
+
+impl<W: Write> Json<'_, W> {
+    fn reborrow(&mut self) -> Json<'_, W> {
+        Json { out: self.out }
+    }
+
+    fn text(&mut self, s: &str) -> Result<(), Error> {
+        self.out.write_char('"')?;
+        for c in s.chars() {
+            match c {
+                '"' => self.out.write_str("\\\"")?,
+                '\\' => self.out.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(self.out, "\\u{:04x}", c as u32)?,
+                c => self.out.write_char(c)?,
+            }
+        }
+        self.out.write_char('"')?;
+        Ok(())
+    }
+
+    fn float(&mut self, v: f64) -> Result<(), Error> {
+        match v.is_finite() {
+            true => write!(self.out, "{}", v)?,
+            false => self.out.write_str("null")?,
+        }
+        Ok(())
+    }
+
+    /// `{"variant": ` before the value of a non-unit variant.
+    fn tag(&mut self, variant: &str) -> Result<(), Error> {
+        self.out.write_char('{')?;
+        self.text(variant)?;
+        self.out.write_char(':')?;
+        Ok(())
+    }
+}
+
+/// Writes the separators between the items of a list or an object and the closing brackets.
+struct Compound<'w, W> {
+    out: &'w mut W,
+    first: bool,
+    close: &'static str,
+}
+
+impl<W: Write> Compound<'_, W> {
+    fn item<T: Serialize + ?Sized>(&mut self, key: Option<&str>, value: &T) -> Result<(), Error> {
+        if !self.first {
+            self.out.write_char(',')?;
+        }
+        self.first = false;
+        let mut json = Json {
+            out: &mut *self.out,
+        };
+        if let Some(key) = key {
+            json.text(key)?;
+            json.out.write_char(':')?;
+        }
+        value.serialize(json)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.out.write_str(self.close)?;
+        Ok(())
+    }
+}
+
+macro_rules! display {
+    ($($method:ident($ty:ty);)*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<(), Error> {
+                write!(self.out, "{}", v)?;
+                Ok(())
+            }
+        )*
+    };
+}
+
+impl<'w, W: Write> ser::Serializer for Json<'w, W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'w, W>;
+    type SerializeTuple = Compound<'w, W>;
+    type SerializeTupleStruct = Compound<'w, W>;
+    type SerializeTupleVariant = Compound<'w, W>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = Compound<'w, W>;
+    type SerializeStructVariant = Compound<'w, W>;
+
+    display! {
+        serialize_bool(bool);
+        serialize_i8(i8);
+        serialize_i16(i16);
+        serialize_i32(i32);
+        serialize_i64(i64);
+        serialize_i128(i128);
+        serialize_u8(u8);
+        serialize_u16(u16);
+        serialize_u32(u32);
+        serialize_u64(u64);
+        serialize_u128(u128);
+    }
+
+    fn serialize_f32(mut self, v: f32) -> Result<(), Error> {
+        self.float(v as f64)
+    }
+
+    fn serialize_f64(mut self, v: f64) -> Result<(), Error> {
+        self.float(v)
+    }
+
+    fn serialize_char(mut self, v: char) -> Result<(), Error> {
+        let mut buf = [0; 4];
+        self.text(v.encode_utf8(&mut buf))
+    }
+
+    fn serialize_str(mut self, v: &str) -> Result<(), Error> {
+        self.text(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for byte in v {
+            ser::SerializeSeq::serialize_element(&mut seq, byte)?;
+        }
+        seq.end()
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.out.write_str("null")?;
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.text(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.tag(variant)?;
+        value.serialize(self.reborrow())?;
+        self.out.write_char('}')?;
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'w, W>, Error> {
+        self.out.write_char('[')?;
+        Ok(Compound {
+            out: self.out,
+            first: true,
+            close: "]",
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'w, W>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'w, W>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'w, W>, Error> {
+        self.tag(variant)?;
+        self.out.write_char('[')?;
+        Ok(Compound {
+            out: self.out,
+            first: true,
+            close: "]}",
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'w, W>, Error> {
+        self.out.write_char('{')?;
+        Ok(Compound {
+            out: self.out,
+            first: true,
+            close: "}",
+        })
+    }
+
+    fn serialize_struct_variant(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'w, W>, Error> {
+        self.tag(variant)?;
+        self.out.write_char('{')?;
+        Ok(Compound {
+            out: self.out,
+            first: true,
+            close: "}}",
+        })
+    }
+
+    fn collect_str<T: fmt::Display + ?Sized>(self, _value: &T) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+impl<W: Write> ser::SerializeSeq for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(None, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl<W: Write> ser::SerializeTuple for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(None, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl<W: Write> ser::SerializeTupleStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(None, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl<W: Write> ser::SerializeTupleVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(None, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.item(Some(key), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl<W: Write> ser::SerializeStructVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.item(Some(key), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
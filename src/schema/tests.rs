@@ -0,0 +1,70 @@
+use heapless::{String, Vec};
+
+use super::*;
+use crate::coms_manager::{Error, Receiver};
+use crate::envelope::tests::samples;
+use crate::messages::{Data, Encrypted};
+use crate::wire;
+
+/// The spec host tools read, write the output of `write_spec` here after changing a message.
+const SPEC: &str = include_str!("../../protocol/schema.json");
+const SPEC_SIZE: usize = 16 * 1024;
+
+const KEY: [u8; 8] = [0x1B, 0x2C, 0x3D, 0x4E, 0x5F, 0x60, 0x71, 0x82];
+
+fn encode(data: &Data) -> Vec<u8, 64> {
+    postcard::to_vec(data).unwrap()
+}
+
+#[test]
+fn the_spec_matches_the_messages() {
+    let mut spec = String::<SPEC_SIZE>::new();
+    write_spec(&mut spec).unwrap();
+    for (line, (ours, theirs)) in spec.lines().zip(SPEC.lines()).enumerate() {
+        assert_eq!(
+            ours,
+            theirs,
+            "protocol/schema.json is stale from line {}",
+            line + 1
+        );
+    }
+    assert_eq!(spec.lines().count(), SPEC.lines().count());
+}
+
+/// Every sample goes on the wire as `ComsManager::send` puts it there, in the clear, sealed and in
+/// fragments, and comes back out of the receiver and into JSON.
+#[test]
+fn every_sample_is_received_and_written_as_json() {
+    for (i, data) in samples().iter().enumerate() {
+        let sealed = Data::Encrypted(Encrypted::seal(&KEY, i as u32, data).unwrap());
+        for (sent, key, fragmented) in [
+            (data, None, false),
+            (&sealed, Some(&KEY), false),
+            (data, None, true),
+            (&sealed, Some(&KEY), true),
+        ] {
+            // a frame of garbage first, the receiver has to find its feet again after it.
+            let mut stream = std::vec::Vec::from([0x13, 0x37, 0xFF]);
+            let out = |byte| stream.push(byte);
+            match fragmented {
+                true => wire::write_fragments(i as u8, sent, PROTOCOL_VERSION, out).unwrap(),
+                false => wire::write(i as u8, sent, PROTOCOL_VERSION, out).unwrap(),
+            }
+
+            let mut receiver = Receiver::new();
+            receiver.set_session_key(key);
+            let mut received = std::vec::Vec::new();
+            for byte in stream {
+                receiver.push(byte);
+                received.extend(core::iter::from_fn(|| receiver.next(0)));
+            }
+            let [Err(Error::Frame), Ok(msg)] = &received[..] else {
+                panic!("sample {i}: {received:?}");
+            };
+            assert_eq!(msg.id, i as u8);
+            assert_eq!(encode(&msg.data), encode(data), "sample {i}");
+            let mut text = String::<1024>::new();
+            json::write(&mut text, msg).unwrap();
+        }
+    }
+}
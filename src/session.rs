@@ -0,0 +1,192 @@
+//! Key exchange state machine
+//!
+//! Pairing runs in two steps: swap RSA public keys, then swap AES session keys encrypted with
+//! them. Both nodes run the same machine. Each one generates its own session key, and when the two
+//! differ the node holding the lower key adopts the peer's, so both settle on the same key without
+//! a designated leader.
+//!
+//! `Session` is pure logic. It is told what happened, with the current time, and answers with the
+//! `Action` the caller should perform. It never touches the link or the key store itself, so it
+//! can be driven step by step off target, see `sim`.
+
+use core::cmp::Ordering;
+
+#[cfg(test)]
+pub mod sim;
+#[cfg(test)]
+mod tests;
+
+/// How long to wait for an answer before asking again.
+pub const TIMEOUT_MS: u32 = 1_000;
+/// Requests sent in a state before giving up.
+pub const MAX_RETRIES: u8 = 5;
+/// How long to stay failed before starting over.
+pub const FAILED_BACKOFF_MS: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    ExchangingPublicKeys,
+    ExchangingSessionKey,
+    Established,
+    /// The session key stopped working, a new one is being fetched.
+    Rekeying,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Nothing,
+    /// Ask for the peer's public key, `Status::UnkownPublicKey`.
+    RequestPublicKey,
+    /// Send our public key, `Data::RSAPublicKey`.
+    SendPublicKey,
+    /// Ask for the peer's session key, `Status::UnkownAESKey`.
+    RequestSessionKey,
+    /// Send our session key encrypted with the peer's public key, `Data::AESKey`.
+    SendSessionKey,
+    /// Replace our session key with the one the peer sent.
+    AdoptPeerSessionKey,
+}
+
+pub struct Session {
+    state: State,
+    /// When the last request went out, or when we failed.
+    since: u32,
+    retries: u8,
+    has_peer_public_key: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            state: State::Idle,
+            since: 0,
+            retries: 0,
+            has_peer_public_key: false,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.state == State::Established
+    }
+
+    /// Start, or start over, from what the key store holds.
+    pub fn start(&mut self, now: u32, has_peer_public_key: bool, keys_match: bool) -> Action {
+        self.has_peer_public_key = has_peer_public_key;
+        if has_peer_public_key && keys_match {
+            self.enter(State::Established, now);
+            return Action::Nothing;
+        }
+        self.exchange(now)
+    }
+
+    /// Resend on timeout, give up after `MAX_RETRIES`, start over after `FAILED_BACKOFF_MS`.
+    pub fn poll(&mut self, now: u32) -> Action {
+        let elapsed = now.wrapping_sub(self.since);
+        match self.state {
+            State::Idle | State::Established => Action::Nothing,
+            State::Failed if elapsed >= FAILED_BACKOFF_MS => self.exchange(now),
+            State::Failed => Action::Nothing,
+            _ if elapsed < TIMEOUT_MS => Action::Nothing,
+            _ if self.retries >= MAX_RETRIES => {
+                self.enter(State::Failed, now);
+                Action::Nothing
+            }
+            _ => {
+                self.retries += 1;
+                self.since = now;
+                self.request()
+            }
+        }
+    }
+
+    /// The peer's public key arrived and was stored.
+    pub fn on_public_key(&mut self, now: u32) -> Action {
+        self.has_peer_public_key = true;
+        if self.state == State::ExchangingPublicKeys {
+            self.enter(State::ExchangingSessionKey, now);
+            return Action::RequestSessionKey;
+        }
+        Action::Nothing
+    }
+
+    /// The peer asked for our public key. Always answered, the peer may have lost it.
+    pub fn on_public_key_requested(&mut self) -> Action {
+        Action::SendPublicKey
+    }
+
+    /// The peer asked for our session key, which we can only send once we know its public key.
+    pub fn on_session_key_requested(&mut self) -> Action {
+        if self.has_peer_public_key {
+            Action::SendSessionKey
+        } else {
+            Action::RequestPublicKey
+        }
+    }
+
+    /// The peer's session key arrived and was stored. `ours` is how our key compares to it.
+    pub fn on_session_key(&mut self, now: u32, ours: Ordering) -> Action {
+        match ours {
+            Ordering::Equal => {
+                self.enter(State::Established, now);
+                Action::Nothing
+            }
+            // the lower key gives way, after adopting the keys match.
+            Ordering::Less => {
+                self.enter(State::Established, now);
+                Action::AdoptPeerSessionKey
+            }
+            // the peer gives way, ask again once it has had the chance to adopt ours.
+            Ordering::Greater => {
+                if self.state != State::Rekeying {
+                    self.state = State::ExchangingSessionKey;
+                }
+                Action::Nothing
+            }
+        }
+    }
+
+    /// Traffic stopped decrypting or a session key was wiped.
+    pub fn on_session_lost(&mut self, now: u32) -> Action {
+        self.enter(State::Rekeying, now);
+        self.request()
+    }
+
+    fn exchange(&mut self, now: u32) -> Action {
+        let state = if self.has_peer_public_key {
+            State::ExchangingSessionKey
+        } else {
+            State::ExchangingPublicKeys
+        };
+        self.enter(state, now);
+        self.request()
+    }
+
+    fn request(&self) -> Action {
+        match self.state {
+            State::ExchangingPublicKeys => Action::RequestPublicKey,
+            State::ExchangingSessionKey | State::Rekeying if !self.has_peer_public_key => {
+                Action::RequestPublicKey
+            }
+            State::ExchangingSessionKey | State::Rekeying => Action::RequestSessionKey,
+            _ => Action::Nothing,
+        }
+    }
+
+    fn enter(&mut self, state: State, now: u32) {
+        self.state = state;
+        self.since = now;
+        self.retries = 0;
+    }
+}
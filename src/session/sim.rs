@@ -0,0 +1,246 @@
+//! Two sessions paired over a simulated link
+//!
+//! `pair` runs two nodes against each other the way `main.rs` drives a session, over a link that
+//! can drop messages, and reports how long they took to agree on a key. Time only moves when the
+//! simulation says so, the same inputs always give the same result. `capture` also writes the run
+//! down in the format of `crate::capture`. Only the host tests build it.
+//!
+//! The nodes exchange the real messages, with the session keys encrypted under the provisioned key
+//! pair the way `main.rs` does it.
+
+use core::fmt::Write;
+use heapless::Vec;
+
+use super::{Action, Session, State};
+use crate::capture::{self, Direction};
+use crate::crypt::{self, RSAKeyPair, RSAPublicKey};
+use crate::fragment::FRAME_SIZE;
+use crate::messages::{Data, Message, Status, PROTOCOL_VERSION};
+use crate::schema;
+
+/// Time between two steps of the simulation.
+pub const STEP_MS: u32 = 10;
+
+pub struct Node {
+    pub session: Session,
+    pub key_pair: RSAKeyPair,
+    pub own_key: [u8; 8],
+    pub peer_key: Option<[u8; 8]>,
+    pub peer_public_key: Option<RSAPublicKey>,
+}
+
+impl Node {
+    /// A node with the provisioned key pair. `own_key` has to stay below its modulus, as
+    /// `crypt::generate_aes_key` keeps it.
+    pub fn new(own_key: [u8; 8]) -> Node {
+        Node {
+            session: Session::new(),
+            key_pair: crypt::PROVISIONED,
+            own_key,
+            peer_key: None,
+            peer_public_key: None,
+        }
+    }
+
+    /// What the session makes of `data` from the peer.
+    pub fn receive(&mut self, now: u32, data: &Data) -> Action {
+        match data {
+            Data::Status(Status::UnkownPublicKey) => self.session.on_public_key_requested(),
+            Data::RSAPublicKey(key) => {
+                self.peer_public_key = Some(key.clone());
+                self.session.on_public_key(now)
+            }
+            Data::Status(Status::UnkownAESKey) => self.session.on_session_key_requested(),
+            Data::AESKey(sealed) => {
+                let key = crypt::decrypt(&self.key_pair.private(), sealed);
+                self.peer_key = Some(key);
+                self.session.on_session_key(now, self.own_key.cmp(&key))
+            }
+            _ => Action::Nothing,
+        }
+    }
+
+    /// What goes on the wire for `action`, the adopt action is applied right away.
+    pub fn perform(&mut self, action: Action) -> Option<Data> {
+        match action {
+            Action::Nothing => None,
+            Action::RequestPublicKey => Some(Data::Status(Status::UnkownPublicKey)),
+            Action::SendPublicKey => Some(Data::RSAPublicKey(self.key_pair.public())),
+            Action::RequestSessionKey => Some(Data::Status(Status::UnkownAESKey)),
+            // the key only goes out encrypted for the peer, which decrypts it with its private key.
+            Action::SendSessionKey => self.peer_public_key.as_ref().map(|peer_public_key| {
+                Data::AESKey(crypt::encrypt(peer_public_key, &self.own_key))
+            }),
+            Action::AdoptPeerSessionKey => {
+                if let Some(key) = self.peer_key {
+                    self.own_key = key;
+                }
+                None
+            }
+        }
+    }
+
+    fn paired_with(&self, other: &Node) -> bool {
+        self.session.is_established()
+            && other.session.is_established()
+            && self.own_key == other.own_key
+    }
+}
+
+/// Start both nodes and step them until both are established on the same key. Every
+/// `drop_every`th message is lost, 0 loses none. Returns the time it took, `None` if they had not
+/// agreed after `limit_ms` or either one failed and `stop_on_failure` is set.
+pub fn pair(
+    nodes: &mut [Node; 2],
+    drop_every: u32,
+    limit_ms: u32,
+    stop_on_failure: bool,
+) -> Option<u32> {
+    run(nodes, drop_every, limit_ms, stop_on_failure, |_, _| {})
+}
+
+/// `pair`, with what node 0 sent and received written to `out` as a capture. Messages go on the
+/// wire as the real ones would.
+pub fn capture<W: Write>(
+    nodes: &mut [Node; 2],
+    drop_every: u32,
+    limit_ms: u32,
+    out: &mut W,
+) -> Result<Option<u32>, schema::Error> {
+    let mut result = Ok(());
+    let paired = run(nodes, drop_every, limit_ms, false, |now, event| {
+        let (direction, id, data) = match event {
+            Event::Sent { from: 0, id, data } => (Direction::Tx, id, data),
+            Event::Delivered { to: 0, id, data } => (Direction::Rx, id, data),
+            _ => return,
+        };
+        if result.is_ok() {
+            result = record(out, now, direction, id, data);
+        }
+    });
+    result.map(|()| paired)
+}
+
+fn record<W: Write>(
+    out: &mut W,
+    now: u32,
+    direction: Direction,
+    id: u8,
+    data: &Data,
+) -> Result<(), schema::Error> {
+    let msg = Message {
+        id,
+        data: data.clone(),
+    };
+    // none of these comes close to filling a frame.
+    let mut bytes = Vec::<u8, FRAME_SIZE>::new();
+    crate::wire::write(id, &msg.data, PROTOCOL_VERSION, |byte| {
+        bytes.push(byte).unwrap()
+    })
+    .unwrap();
+    capture::write_bytes(out, now, direction, &bytes)?;
+    capture::write_message(out, now, direction, &msg)
+}
+
+/// What `run` reports to its tap, `id` counts the messages each node sent.
+enum Event<'a> {
+    Sent { from: usize, id: u8, data: &'a Data },
+    Delivered { to: usize, id: u8, data: &'a Data },
+}
+
+/// Messages between the two nodes. Those sent during one step arrive at the start of the next,
+/// tagged with their receiver.
+struct Link {
+    in_flight: Vec<(usize, u8, Data), 16>,
+    sent: u32,
+    ids: [u8; 2],
+    drop_every: u32,
+}
+
+impl Link {
+    fn send(
+        &mut self,
+        now: u32,
+        from: usize,
+        data: Option<Data>,
+        tap: &mut impl FnMut(u32, Event),
+    ) {
+        let Some(data) = data else {
+            return;
+        };
+        let id = self.ids[from];
+        self.ids[from] = id.wrapping_add(1);
+        tap(
+            now,
+            Event::Sent {
+                from,
+                id,
+                data: &data,
+            },
+        );
+        self.sent += 1;
+        if self.drop_every == 0 || !self.sent.is_multiple_of(self.drop_every) {
+            // a full link drops the message, the same as losing it.
+            self.in_flight.push((1 - from, id, data)).ok();
+        }
+    }
+}
+
+fn run(
+    nodes: &mut [Node; 2],
+    drop_every: u32,
+    limit_ms: u32,
+    stop_on_failure: bool,
+    mut tap: impl FnMut(u32, Event),
+) -> Option<u32> {
+    let mut link = Link {
+        in_flight: Vec::new(),
+        sent: 0,
+        ids: [0; 2],
+        drop_every,
+    };
+
+    for (i, node) in nodes.iter_mut().enumerate() {
+        let has_peer_public_key = node.peer_public_key.is_some();
+        let keys_match = node.peer_key == Some(node.own_key);
+        let action = node.session.start(0, has_peer_public_key, keys_match);
+        let data = node.perform(action);
+        link.send(0, i, data, &mut tap);
+    }
+
+    let mut now = 0;
+    while now <= limit_ms {
+        if nodes[0].paired_with(&nodes[1]) {
+            return Some(now);
+        }
+        if stop_on_failure
+            && nodes
+                .iter()
+                .any(|node| node.session.state() == State::Failed)
+        {
+            return None;
+        }
+        now += STEP_MS;
+
+        let arriving = core::mem::take(&mut link.in_flight);
+        for (to, id, data) in arriving {
+            tap(
+                now,
+                Event::Delivered {
+                    to,
+                    id,
+                    data: &data,
+                },
+            );
+            let action = nodes[to].receive(now, &data);
+            let data = nodes[to].perform(action);
+            link.send(now, to, data, &mut tap);
+        }
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let action = node.session.poll(now);
+            let data = node.perform(action);
+            link.send(now, i, data, &mut tap);
+        }
+    }
+    None
+}
@@ -0,0 +1,63 @@
+use super::sim::{self, Node};
+use super::*;
+
+const LOW: [u8; 8] = [0x11; 8];
+const HIGH: [u8; 8] = [0x22; 8];
+
+fn nodes() -> [Node; 2] {
+    [Node::new(LOW), Node::new(HIGH)]
+}
+
+#[test]
+fn two_nodes_settle_on_the_higher_key() {
+    let mut nodes = nodes();
+    let paired = sim::pair(&mut nodes, 0, 5_000, true);
+    assert!(paired.is_some());
+    assert_eq!(nodes[0].own_key, HIGH);
+    assert_eq!(nodes[1].own_key, HIGH);
+    // each one decrypted what the other sent, node 1 last got the key node 0 adopted.
+    assert_eq!(nodes[0].peer_key, Some(HIGH));
+    assert_eq!(nodes[1].peer_key, Some(HIGH));
+}
+
+#[test]
+fn lost_messages_are_asked_for_again() {
+    for drop_every in [4, 5] {
+        let mut nodes = nodes();
+        let paired = sim::pair(&mut nodes, drop_every, 10_000, true);
+        assert!(paired.unwrap() > TIMEOUT_MS, "dropping every {drop_every}");
+        assert_eq!(nodes[0].own_key, nodes[1].own_key);
+    }
+}
+
+#[test]
+fn a_dead_link_fails_after_the_retries() {
+    let mut nodes = nodes();
+    assert_eq!(sim::pair(&mut nodes, 1, 60_000, true), None);
+    assert!(nodes
+        .iter()
+        .all(|node| node.session.state() == State::Failed));
+}
+
+#[test]
+fn session_keys_never_go_out_in_the_clear() {
+    let mut text = heapless::String::<8192>::new();
+    sim::capture(&mut nodes(), 0, 5_000, &mut text).unwrap();
+    assert!(text.contains("AESKey"));
+    for key in [LOW, HIGH] {
+        let mut json = heapless::String::<64>::new();
+        crate::schema::json::write(&mut json, &key).unwrap();
+        assert!(!text.contains(json.as_str()), "{json} in\n{text}");
+    }
+}
+
+#[test]
+fn known_keys_skip_the_exchange() {
+    let mut session = Session::new();
+    assert_eq!(session.start(0, true, true), Action::Nothing);
+    assert!(session.is_established());
+
+    let mut session = Session::new();
+    assert_eq!(session.start(0, true, false), Action::RequestSessionKey);
+    assert_eq!(session.state(), State::ExchangingSessionKey);
+}
@@ -0,0 +1,44 @@
+//! Periodic temperature broadcast
+//!
+//! A sensor node samples its sensor on a fixed period and sends the reading to the monitor. The
+//! link encrypts it like any other message once the nodes share a session key.
+
+use crate::config::{Config, Role};
+
+/// Tells the main loop when the next reading is due.
+pub struct Scheduler {
+    period_ms: u32,
+    last: Option<u32>,
+}
+
+impl Scheduler {
+    pub fn new(period_ms: u32) -> Scheduler {
+        Scheduler {
+            period_ms,
+            last: None,
+        }
+    }
+
+    pub fn set_period(&mut self, period_ms: u32) {
+        self.period_ms = period_ms;
+    }
+
+    /// True once per period, the first call is always due. `now` may wrap.
+    pub fn due(&mut self, now: u32) -> bool {
+        match self.last {
+            Some(last) if now.wrapping_sub(last) < self.period_ms => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// How often a sensor node broadcasts, monitors never do.
+pub fn period(config: &Config) -> u32 {
+    match config.role {
+        Role::Sensor { period_ms } => period_ms as u32,
+        Role::Monitor => u32::MAX,
+    }
+}
@@ -0,0 +1,85 @@
+//! Calibrated temperature from the sensor on the mux
+//!
+//! The sensor is a linear analog part, TMP36 style: 500 mV at 0 °C and 10 mV per degree. Counts go
+//! through the oversampling and filtering pipeline and are turned into millivolts against the
+//! supply measured through VREFINT, so a sagging rail does not read as a temperature change. A
+//! per-board two-point calibration then corrects gain and offset.
+//!
+//! The calibration has its own block right after the config.
+
+use core::convert::Infallible;
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::filter::{Pipeline, Sampling, FRACTION_BITS};
+use crate::mux::{Channel, IoLine, Mux};
+
+mod calibration;
+
+pub use calibration::*;
+
+const OFFSET_MV: f32 = 500.0;
+const MV_PER_DEGREE: f32 = 10.0;
+const FULL_SCALE: f32 = (4095 << FRACTION_BITS) as f32; // 12 bit conversions in Q4
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The sensor conversion failed.
+    Adc,
+    /// The supply could not be measured.
+    Supply,
+}
+
+pub struct TemperatureSensor {
+    calibration: Calibration,
+    pipeline: Pipeline,
+}
+
+impl TemperatureSensor {
+    pub fn new(calibration: Calibration, sampling: Sampling) -> TemperatureSensor {
+        TemperatureSensor {
+            calibration,
+            pipeline: Pipeline::new(sampling),
+        }
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        if sampling != self.pipeline.sampling() {
+            self.pipeline.set_sampling(sampling);
+        }
+    }
+
+    /// Temperature in °C.
+    pub fn read_celsius<S0, S1, Io>(&mut self, mux: &mut Mux<S0, S1, Io>) -> Result<f32, Error>
+    where
+        S0: OutputPin<Error = Infallible>,
+        S1: OutputPin<Error = Infallible>,
+        Io: IoLine,
+    {
+        let measured = self.read_measured(mux)?;
+        Ok(self.calibration.apply(measured))
+    }
+
+    /// Temperature in °C before calibration, what a `CalibrationPoint` records as `measured`.
+    pub fn read_measured<S0, S1, Io>(&mut self, mux: &mut Mux<S0, S1, Io>) -> Result<f32, Error>
+    where
+        S0: OutputPin<Error = Infallible>,
+        S1: OutputPin<Error = Infallible>,
+        Io: IoLine,
+    {
+        let supply_mv = mux.supply_mv().ok_or(Error::Supply)? as f32;
+        let counts = self
+            .pipeline
+            .sample(|| mux.execute(Channel::TempSensor))
+            .ok_or(Error::Adc)? as f32;
+        let millivolts = counts * supply_mv / FULL_SCALE;
+        Ok((millivolts - OFFSET_MV) / MV_PER_DEGREE)
+    }
+}
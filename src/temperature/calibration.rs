@@ -0,0 +1,92 @@
+//! The per-board calibration and where it is kept
+//!
+//! Only data and the EEPROM block, no sensor, so the host tools can build the messages that carry
+//! it.
+
+use crate::eeprom::block::{self, Block};
+use crate::eeprom::{Device, Storage};
+use crate::key_store;
+
+/// Bump whenever `Calibration` changes shape, older records then fall back to the defaults.
+pub const CALIBRATION_VERSION: u8 = 1;
+
+// Right above the key store's staging area, the only place left for it on the AT25010B.
+const BLOCK: Block = Block::new(key_store::STAGING_END as u16, 16, CALIBRATION_VERSION);
+
+pub const CALIBRATION_START: usize = BLOCK.address as usize;
+
+/// End of the calibration block, the config block follows on parts with room for it.
+pub const CALIBRATION_END: usize = BLOCK.end();
+
+/// Longest postcard encoding of a `Calibration`, four `i16`s of up to 3 bytes each.
+const MAX_ENCODED_LEN: usize = 4 * 3;
+
+// The block must hold any calibration and stay clear of the identity slot even on the AT25010B.
+const _: () = {
+    assert!(BLOCK.max_payload_len() >= MAX_ENCODED_LEN);
+    assert!(CALIBRATION_END <= key_store::free_end(Device::AT25010B.capacity));
+};
+
+#[cfg(test)]
+mod tests;
+
+/// One reference point, both values in hundredths of a degree.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationPoint {
+    /// What the uncalibrated sensor read.
+    pub measured: i16,
+    /// What a reference thermometer read at the same time.
+    pub actual: i16,
+}
+
+impl CalibrationPoint {
+    /// `(measured, actual)` in °C.
+    fn celsius(self) -> (f32, f32) {
+        (self.measured as f32 / 100.0, self.actual as f32 / 100.0)
+    }
+}
+
+/// Two-point calibration, readings are mapped linearly through both points.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    pub low: CalibrationPoint,
+    pub high: CalibrationPoint,
+}
+
+impl Default for Calibration {
+    /// Leaves readings untouched.
+    fn default() -> Self {
+        Calibration {
+            low: CalibrationPoint {
+                measured: 0,
+                actual: 0,
+            },
+            high: CalibrationPoint {
+                measured: 10_000,
+                actual: 10_000,
+            },
+        }
+    }
+}
+
+impl Calibration {
+    /// Read the calibration from the chip, or the defaults if there is no valid record.
+    pub fn load<S: Storage>(storage: &mut S) -> Calibration {
+        BLOCK.load(storage).unwrap_or_default()
+    }
+
+    /// Write the calibration to the chip, skipped when the stored record is already identical.
+    pub fn store<S: Storage>(&self, storage: &mut S) -> Result<(), block::Error> {
+        BLOCK.store(storage, self)
+    }
+
+    /// Map an uncalibrated reading, in °C. Two points at the same reading cannot define a line,
+    /// the reading is then passed through.
+    pub fn apply(&self, measured: f32) -> f32 {
+        if self.low.measured == self.high.measured {
+            return measured;
+        }
+        let (low, high) = (self.low.celsius(), self.high.celsius());
+        low.1 + (measured - low.0) * (high.1 - low.1) / (high.0 - low.0)
+    }
+}
@@ -0,0 +1,80 @@
+use super::*;
+use crate::config;
+use crate::crypt;
+use crate::eeprom::sim::SimEeprom;
+use crate::key_store::{KeyStore, OwnAesKey, OwnKeyPair};
+
+fn extremes() -> Calibration {
+    Calibration {
+        low: CalibrationPoint {
+            measured: i16::MIN,
+            actual: i16::MAX,
+        },
+        high: CalibrationPoint {
+            measured: i16::MAX,
+            actual: i16::MIN,
+        },
+    }
+}
+
+#[test]
+fn the_largest_calibration_is_no_longer_than_counted() {
+    let mut buf = [0u8; 32];
+    let encoded = postcard::to_slice(&extremes(), &mut buf).unwrap();
+    assert_eq!(encoded.len(), MAX_ENCODED_LEN);
+}
+
+#[test]
+fn a_calibration_reads_back_after_a_reboot() {
+    let mut sim = SimEeprom::default();
+    assert_eq!(Calibration::load(&mut sim), Calibration::default());
+    extremes().store(&mut sim).unwrap();
+    assert_eq!(Calibration::load(&mut sim), extremes());
+}
+
+#[test]
+fn storing_the_calibration_leaves_the_config_alone() {
+    let mut sim = SimEeprom::new(Device::AT25020B);
+    let config = config::Config {
+        temp_threshold: -400,
+        ..config::Config::default()
+    };
+    config.store(&mut sim).unwrap();
+    extremes().store(&mut sim).unwrap();
+    assert_eq!(config::Config::load(&mut sim), config);
+}
+
+/// On the AT25010B the block ends where the identity slot starts.
+#[test]
+fn storing_the_calibration_leaves_the_keys_alone() {
+    let mut sim = SimEeprom::new(Device::AT25010B);
+    let kek = || crypt::derive_kek(b"test-board-1");
+    let mut key_store = KeyStore::new(&mut sim, kek());
+    key_store.provision_identity(&crypt::PROVISIONED).unwrap();
+    key_store.put(OwnAesKey, &[0x5A; 8]).unwrap();
+    extremes().store(key_store.storage()).unwrap();
+
+    let mut key_store = KeyStore::new(&mut sim, kek());
+    let key_pair = key_store.get(OwnKeyPair).unwrap().unwrap();
+    assert_eq!(key_pair.to_bytes(), crypt::PROVISIONED.to_bytes());
+    assert_eq!(*key_store.get(OwnAesKey).unwrap().unwrap(), [0x5A; 8]);
+    assert_eq!(Calibration::load(key_store.storage()), extremes());
+}
+
+#[test]
+fn readings_map_through_both_points() {
+    let calibration = Calibration {
+        low: CalibrationPoint {
+            measured: 1_000,
+            actual: 1_200,
+        },
+        high: CalibrationPoint {
+            measured: 5_000,
+            actual: 5_600,
+        },
+    };
+    assert_eq!(calibration.apply(10.0), 12.0);
+    assert_eq!(calibration.apply(50.0), 56.0);
+    assert_eq!(calibration.apply(30.0), 34.0);
+    assert_eq!(Calibration::default().apply(21.5), 21.5);
+}
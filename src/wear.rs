@@ -0,0 +1,213 @@
+//! Persisted EEPROM write counters
+//!
+//! `Counted` sits between the driver and everything that writes to the chip, and keeps a counter
+//! for every region of the array: the key slots, the staging area, the calibration block, the config
+//! block and the event log. A write counts once against each region it lands in, however many pages
+//! it spans, since it programs each byte at most once. The counts reach the chip before the write
+//! itself, so one cut short by a power cut is counted too. That makes each count an upper bound on
+//! the write cycles any byte of its region has taken, and a wipe or a torn record leaves it alone.
+//!
+//! The counts are kept in two copies of `[version, count (3 bytes) per region, crc]`, written in
+//! turn. The complete copy with the larger total is the current one, so a power cut while one is
+//! written leaves the other. The copies themselves are not counted, each takes half of the writes
+//! that are.
+//!
+//! The copies sit between the config block and the event log. The AT25010B has no room for them,
+//! nothing is counted there.
+
+use crate::config;
+use crate::eeprom::{self, crc8, BlockProtect, Device, Storage};
+use crate::key_store::{self, Slot};
+use crate::messages::WearReport;
+use crate::temperature;
+
+#[cfg(test)]
+mod tests;
+
+/// Bump whenever the record layout changes, older counts then start again from zero.
+const VERSION: u8 = 1;
+
+const COUNT_LEN: usize = 3;
+const RECORD_LEN: usize = 1 + Region::ALL.len() * COUNT_LEN + 1; // version + counts + crc
+
+/// Counts stop here, far beyond the rated endurance.
+const MAX_COUNT: u32 = 0x00FF_FFFF;
+
+const START: u16 = config::END as u16;
+
+/// End of the second copy, the event log follows.
+pub const END: usize = START as usize + 2 * RECORD_LEN;
+
+// Both copies must fit below the identity slot on every part that has a config block.
+const _: () = assert!(END <= key_store::free_end(Device::AT25020B.capacity));
+
+/// A part of the array with a counter of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Slot(Slot),
+    Staging,
+    Calibration,
+    Config,
+    Log,
+}
+
+impl Region {
+    pub const ALL: [Region; 8] = [
+        Region::Slot(Slot::OwnAesKey),
+        Region::Slot(Slot::OwnKeyPair),
+        Region::Slot(Slot::PeerAesKey),
+        Region::Slot(Slot::PeerPublicKey),
+        Region::Staging,
+        Region::Calibration,
+        Region::Config,
+        Region::Log,
+    ];
+
+    /// Where the region starts and ends on a part of `capacity` bytes.
+    fn bounds(self, capacity: usize) -> (usize, usize) {
+        match self {
+            Region::Slot(slot) => {
+                let address = slot.address(capacity) as usize;
+                (address, address + slot.record_len())
+            }
+            Region::Staging => (key_store::STAGING_ADDRESS as usize, key_store::STAGING_END),
+            Region::Calibration => (temperature::CALIBRATION_START, temperature::CALIBRATION_END),
+            Region::Config => (config::START, config::END),
+            Region::Log => (END, key_store::free_end(capacity)),
+        }
+    }
+}
+
+pub struct Counted<S: Storage> {
+    storage: S,
+    /// `None` on a part without room for the counts.
+    counts: Option<[u32; Region::ALL.len()]>,
+    /// The copy the counts were last read from or written to, the next update goes to the other.
+    current: usize,
+}
+
+impl<S: Storage> Counted<S> {
+    /// Read the counts back from whichever copy is current, a fresh part starts from zero.
+    pub fn new(storage: S) -> Counted<S> {
+        let mut counted = Counted {
+            counts: None,
+            current: 1,
+            storage,
+        };
+        if END > key_store::free_end(counted.storage.device().capacity) {
+            return counted;
+        }
+        let mut counts = [0; Region::ALL.len()];
+        let mut newest = None;
+        for copy in 0..2 {
+            let Some(read) = counted.read(copy) else {
+                continue;
+            };
+            let total: u64 = read.iter().map(|&count| count as u64).sum();
+            if newest.is_none_or(|newest| total > newest) {
+                newest = Some(total);
+                counts = read;
+                counted.current = copy;
+            }
+        }
+        counted.counts = Some(counts);
+        counted
+    }
+
+    /// Writes counted against each region, `None` on a part without room for the counts.
+    pub fn report(&self) -> Option<WearReport> {
+        let counts = self.counts?;
+        let mut report = WearReport {
+            endurance: eeprom::ENDURANCE,
+            ..WearReport::default()
+        };
+        for (region, count) in Region::ALL.into_iter().zip(counts) {
+            match region {
+                Region::Slot(slot) => report.slots[slot.index() as usize] = count,
+                Region::Staging => report.staging = count,
+                Region::Calibration => report.calibration = count,
+                Region::Config => report.config = count,
+                Region::Log => report.log = count,
+            }
+        }
+        Some(report)
+    }
+
+    /// Count a write of `len` bytes at `address` and write the counts to the other copy. Bytes in
+    /// the protected range never reach the array, so they are not counted.
+    fn count(&mut self, address: u16, len: usize) -> Result<(), eeprom::Error> {
+        let Some(counts) = &mut self.counts else {
+            return Ok(());
+        };
+        let capacity = self.storage.device().capacity;
+        let start = address as usize;
+        let end = match self.storage.block_protect().start(capacity) {
+            Some(protected) => (start + len).min(protected as usize),
+            None => start + len,
+        };
+        let mut counted = false;
+        for (region, count) in Region::ALL.into_iter().zip(counts.iter_mut()) {
+            let (region_start, region_end) = region.bounds(capacity);
+            if start < region_end && region_start < end {
+                *count = (*count + 1).min(MAX_COUNT);
+                counted = true;
+            }
+        }
+        if !counted {
+            return Ok(());
+        }
+
+        let mut record = [0u8; RECORD_LEN];
+        record[0] = VERSION;
+        for (i, count) in counts.iter().enumerate() {
+            let at = 1 + i * COUNT_LEN;
+            record[at..at + COUNT_LEN].copy_from_slice(&count.to_le_bytes()[..COUNT_LEN]);
+        }
+        record[RECORD_LEN - 1] = crc8(&record[..RECORD_LEN - 1]);
+        let copy = 1 - self.current;
+        self.storage.write_bytes(Self::address(copy), &record)?;
+        self.current = copy;
+        Ok(())
+    }
+
+    fn read(&mut self, copy: usize) -> Option<[u32; Region::ALL.len()]> {
+        let mut record = [0u8; RECORD_LEN];
+        self.storage.read_bytes(Self::address(copy), &mut record);
+        if record[0] != VERSION || record[RECORD_LEN - 1] != crc8(&record[..RECORD_LEN - 1]) {
+            return None;
+        }
+        let mut counts = [0; Region::ALL.len()];
+        for (i, count) in counts.iter_mut().enumerate() {
+            let at = 1 + i * COUNT_LEN;
+            *count = u32::from_le_bytes([record[at], record[at + 1], record[at + 2], 0]);
+        }
+        Some(counts)
+    }
+
+    fn address(copy: usize) -> u16 {
+        START + (copy * RECORD_LEN) as u16
+    }
+}
+
+impl<S: Storage> Storage for Counted<S> {
+    fn device(&self) -> Device {
+        self.storage.device()
+    }
+
+    fn read_bytes(&mut self, address: u16, buf: &mut [u8]) {
+        self.storage.read_bytes(address, buf)
+    }
+
+    fn write_bytes(&mut self, address: u16, data: &[u8]) -> Result<(), eeprom::Error> {
+        self.count(address, data.len())?;
+        self.storage.write_bytes(address, data)
+    }
+
+    fn block_protect(&mut self) -> BlockProtect {
+        self.storage.block_protect()
+    }
+
+    fn set_block_protect(&mut self, protect: BlockProtect) {
+        self.storage.set_block_protect(protect)
+    }
+}
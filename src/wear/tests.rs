@@ -0,0 +1,118 @@
+use super::*;
+use crate::config::Config;
+use crate::crypt;
+use crate::eeprom::sim::SimEeprom;
+use crate::key_store::{KeyStore, OwnAesKey, PeerAesKey};
+use crate::temperature::Calibration;
+
+const DEVICE_ID: [u8; 12] = *b"test-board-1";
+const KEY: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x08];
+const OTHER: [u8; 8] = [0x81, 0x92, 0xA3, 0xB4, 0xC5, 0xD6, 0xE7, 0x78];
+
+fn open(sim: &mut SimEeprom) -> KeyStore<Counted<&mut SimEeprom>> {
+    KeyStore::new(Counted::new(sim), crypt::derive_kek(&DEVICE_ID))
+}
+
+fn report(sim: &mut SimEeprom) -> WearReport {
+    Counted::new(sim).report().unwrap()
+}
+
+fn total(report: &WearReport) -> u32 {
+    report.slots.iter().sum::<u32>()
+        + report.staging
+        + report.config
+        + report.calibration
+        + report.log
+}
+
+#[test]
+fn each_write_counts_against_the_region_it_lands_in() {
+    let mut sim = SimEeprom::new(Device::AT25040B);
+    let fresh = report(&mut sim);
+    assert_eq!(total(&fresh), 0);
+    assert_eq!(fresh.endurance, eeprom::ENDURANCE);
+
+    open(&mut sim).put(OwnAesKey, &KEY).unwrap();
+    let keys = report(&mut sim);
+    assert!(keys.slots[Slot::OwnAesKey.index() as usize] > 0);
+    assert!(keys.staging > 0);
+    assert_eq!(keys.slots[Slot::PeerAesKey.index() as usize], 0);
+    assert_eq!((keys.config, keys.calibration, keys.log), (0, 0, 0));
+
+    let mut counted = Counted::new(&mut sim);
+    Config::default().store(&mut counted).unwrap();
+    Calibration::default().store(&mut counted).unwrap();
+    counted.write_bytes(END as u16, &[0; 16]).unwrap();
+
+    let all = report(&mut sim);
+    assert_eq!(all.slots, keys.slots);
+    assert_eq!(all.staging, keys.staging);
+    assert_eq!((all.config, all.calibration), (1, 1));
+    assert_eq!(all.log, 1);
+}
+
+#[test]
+fn counts_survive_a_wipe_and_a_reboot() {
+    let mut sim = SimEeprom::new(Device::AT25020B);
+    let mut key_store = open(&mut sim);
+    key_store.put(OwnAesKey, &KEY).unwrap();
+    key_store.put(PeerAesKey, &OTHER).unwrap();
+    let before = key_store.storage().report().unwrap();
+
+    key_store.wipe_all();
+    let after = report(&mut sim);
+    for (before, after) in before.slots.iter().zip(after.slots) {
+        assert!(after >= *before);
+    }
+    assert!(after.slots[Slot::OwnAesKey.index() as usize] > 0);
+    assert!(total(&after) > total(&before));
+}
+
+#[test]
+fn a_power_cut_at_any_byte_loses_no_count() {
+    let mut sim = SimEeprom::new(Device::AT25020B);
+    open(&mut sim).put(OwnAesKey, &KEY).unwrap();
+    let before = report(&mut sim);
+    let written = sim.bytes_written();
+    open(&mut sim).put(OwnAesKey, &OTHER).unwrap();
+    let update = sim.bytes_written() - written;
+
+    for cut_after in 0..=update {
+        let mut sim = SimEeprom::new(Device::AT25020B);
+        open(&mut sim).put(OwnAesKey, &KEY).unwrap();
+
+        sim.cut_power_after(cut_after);
+        open(&mut sim).put(OwnAesKey, &OTHER).unwrap();
+        sim.restore_power();
+
+        // whatever the reboot finishes or rolls back, the counts never go backwards.
+        let mut key_store = open(&mut sim);
+        let after = key_store.storage().report().unwrap();
+        for (before, after) in before.slots.iter().zip(after.slots) {
+            assert!(after >= *before, "cut after {cut_after} bytes");
+        }
+        assert!(
+            after.staging >= before.staging,
+            "cut after {cut_after} bytes"
+        );
+
+        // and they keep counting.
+        key_store.put(PeerAesKey, &KEY).unwrap();
+        assert!(total(&report(&mut sim)) > total(&after));
+    }
+}
+
+#[test]
+fn the_smallest_part_counts_nothing() {
+    let mut plain = SimEeprom::new(Device::AT25010B);
+    KeyStore::new(&mut plain, crypt::derive_kek(&DEVICE_ID))
+        .put(OwnAesKey, &KEY)
+        .unwrap();
+
+    let mut sim = SimEeprom::new(Device::AT25010B);
+    let mut key_store = open(&mut sim);
+    key_store.put(OwnAesKey, &KEY).unwrap();
+    assert!(key_store.storage().report().is_none());
+    assert_eq!(sim.memory(), plain.memory());
+    assert_eq!(sim.bytes_written(), plain.bytes_written());
+}
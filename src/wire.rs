@@ -0,0 +1,67 @@
+//! Messages on the serial line
+//!
+//! A message goes out as one COBS frame if it fits, in fragments under the same id if it does not.
+//! Each frame is byte stuffed, a 0xFF in it is sent as 0xFE 0xFF, and closed with a 0xFF stop
+//! byte. `ComsManager` and the host tools put messages on the line through `write`, the
+//! `coms_manager::Receiver` takes them off again.
+
+use crate::fragment::{self, FRAME_SIZE, MAX_MESSAGE};
+use crate::messages::{Data, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Larger than `MAX_MESSAGE`, nothing was written.
+    TooLarge,
+    /// Too large for a frame, and the peer's protocol version has no fragments.
+    Unsupported,
+}
+
+/// Write `data` under `id`, in fragments if it does not fit a frame. Every byte goes to `out`.
+pub fn write(id: u8, data: &Data, peer_version: u16, out: impl FnMut(u8)) -> Result<(), Error> {
+    let msg = Message {
+        id,
+        data: data.clone(),
+    };
+    let mut frame = [0; FRAME_SIZE];
+    match postcard::to_slice_cobs(&msg, &mut frame) {
+        Ok(frame) => {
+            stuff(frame, out);
+            Ok(())
+        }
+        Err(_) => write_fragments(id, data, peer_version, out),
+    }
+}
+
+/// Write `data` under `id` split into fragments, even if it would fit a frame.
+pub fn write_fragments(
+    id: u8,
+    data: &Data,
+    peer_version: u16,
+    mut out: impl FnMut(u8),
+) -> Result<(), Error> {
+    let mut encoded = [0; MAX_MESSAGE];
+    let encoded = postcard::to_slice(data, &mut encoded).map_err(|_| Error::TooLarge)?;
+    let fragments = fragment::split(encoded).ok_or(Error::TooLarge)?;
+    for fragment in fragments {
+        let data = Data::Fragment(fragment);
+        if data.since() > peer_version {
+            return Err(Error::Unsupported);
+        }
+        let mut frame = [0; FRAME_SIZE];
+        // a full fragment always fits, see the `fragment` tests.
+        let frame = postcard::to_slice_cobs(&Message { id, data }, &mut frame).unwrap();
+        stuff(frame, &mut out);
+    }
+    Ok(())
+}
+
+/// Byte stuffing, then the stop byte.
+pub fn stuff(frame: &[u8], mut out: impl FnMut(u8)) {
+    for &byte in frame {
+        if byte == 0xFF {
+            out(0xFE);
+        }
+        out(byte);
+    }
+    out(0xFF);
+}